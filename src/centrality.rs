@@ -0,0 +1,284 @@
+//! Centralities computed directly on the adjacency list.
+//!
+//! Exact betweenness and closeness need shortest paths between all pairs of nodes, which is too
+//! slow for big crawls. Here Brandes' algorithm is run only from `k` randomly chosen pivot nodes
+//! and the results are extrapolated to the whole graph. PageRank is computed here as well.
+
+use std::{collections::VecDeque, thread};
+
+use rand::{rngs::StdRng, seq::index, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{AnalysisConfiguration, CentralityMode};
+
+/// Parameters used to approximate centralities - reported in the output, so it's known how
+/// precise the values are.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CentralityApproximation {
+    /// Number of pivot nodes shortest paths were computed from
+    pub pivots: usize,
+    /// Number of nodes in the graph
+    pub nodes: usize,
+    /// Expected approximation error the number of pivots was derived from (if used)
+    pub error_bound: Option<f64>,
+    /// Seed used to choose pivots
+    pub seed: u64,
+}
+
+impl CentralityApproximation {
+    /// Determine approximation parameters for the graph with given number of nodes. Returns None
+    /// if centralities are computed exactly.
+    pub fn from_config(config: &AnalysisConfiguration, nodes: usize) -> Option<Self> {
+        if config.centrality_mode != CentralityMode::Approximate {
+            return None;
+        }
+
+        // Number of pivots derived from the error bound follows Eppstein and Wang - with
+        // log(n) / e^2 pivots the error is below e * diameter with high probability.
+        let (pivots, error_bound) = match (config.sample_size, config.error_bound) {
+            (Some(sample_size), _) => (sample_size, None),
+            (None, Some(error_bound)) => (
+                ((nodes.max(2) as f64).ln() / (error_bound * error_bound)).ceil() as usize,
+                Some(error_bound),
+            ),
+            (None, None) => return None,
+        };
+
+        Some(CentralityApproximation {
+            pivots: pivots.clamp(1, nodes.max(1)),
+            nodes,
+            error_bound,
+            seed: config.seed,
+        })
+    }
+}
+
+/// Approximate betweenness and closeness centralities of all nodes of the undirected graph
+/// given as an adjacency list. Values are in the same scale as the exact ones: betweenness is
+/// the (estimated) number of shortest paths going through the node and closeness is the mean
+/// distance to the other reachable nodes.
+pub fn approximate_centralities(
+    adjacency: &[Vec<usize>],
+    approximation: &CentralityApproximation,
+    num_threads: usize,
+) -> (Vec<f64>, Vec<f64>) {
+    let nodes = adjacency.len();
+    if nodes == 0 {
+        return (Vec::new(), Vec::new());
+    }
+
+    let mut rng = StdRng::seed_from_u64(approximation.seed);
+    let pivots = index::sample(&mut rng, nodes, approximation.pivots.min(nodes)).into_vec();
+
+    let chunk_size = pivots.len().div_ceil(num_threads.max(1));
+    let partials = thread::scope(|scope| {
+        let handles = pivots
+            .chunks(chunk_size)
+            .map(|chunk| scope.spawn(|| accumulate_from_pivots(adjacency, chunk)))
+            .collect::<Vec<_>>();
+
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("centrality worker panicked"))
+            .collect::<Vec<_>>()
+    });
+
+    let mut betweenness = vec![0.0; nodes];
+    let mut distance_sums = vec![0.0; nodes];
+    let mut reached_pivots = vec![0usize; nodes];
+    for partial in partials {
+        for idx in 0..nodes {
+            betweenness[idx] += partial.dependencies[idx];
+            distance_sums[idx] += partial.distance_sums[idx];
+            reached_pivots[idx] += partial.reached_pivots[idx];
+        }
+    }
+
+    // Each pivot stands for nodes / pivots sources. Paths are counted from both ends in the
+    // undirected graph, hence the division by 2.
+    let scale = nodes as f64 / pivots.len() as f64 / 2.0;
+    for value in betweenness.iter_mut() {
+        *value *= scale;
+    }
+
+    let closeness = distance_sums
+        .iter()
+        .zip(reached_pivots)
+        .map(|(sum, count)| if count > 0 { sum / count as f64 } else { 0.0 })
+        .collect();
+
+    (betweenness, closeness)
+}
+
+/// Compute PageRank of all nodes of the undirected graph given as an adjacency list. Rank of the
+/// nodes without connections is spread evenly over the whole graph. Ranks sum up to 1.
+pub fn pagerank(adjacency: &[Vec<usize>], damping: f64) -> Vec<f64> {
+    const MAX_ITERATIONS: usize = 100;
+    const TOLERANCE: f64 = 1e-10;
+
+    let nodes = adjacency.len();
+    if nodes == 0 {
+        return Vec::new();
+    }
+
+    let mut rank = vec![1.0 / nodes as f64; nodes];
+    let mut next_rank = vec![0.0; nodes];
+    for _ in 0..MAX_ITERATIONS {
+        let dangling_rank: f64 = adjacency
+            .iter()
+            .zip(&rank)
+            .filter(|(peers, _)| peers.is_empty())
+            .map(|(_, rank)| rank)
+            .sum();
+
+        next_rank.fill((1.0 - damping + damping * dangling_rank) / nodes as f64);
+        for (node, peers) in adjacency.iter().enumerate() {
+            let share = damping * rank[node] / peers.len() as f64;
+            for &peer in peers {
+                next_rank[peer] += share;
+            }
+        }
+
+        let change: f64 = rank
+            .iter()
+            .zip(&next_rank)
+            .map(|(old, new)| (old - new).abs())
+            .sum();
+        std::mem::swap(&mut rank, &mut next_rank);
+        if change < TOLERANCE {
+            break;
+        }
+    }
+
+    rank
+}
+
+/// Partial results of the shortest paths searches from some pivots.
+struct PivotSums {
+    /// Sum of the pair dependencies for each node
+    dependencies: Vec<f64>,
+    /// Sum of distances to the reached pivots for each node
+    distance_sums: Vec<f64>,
+    /// Number of pivots each node was reached from (excluding the node itself)
+    reached_pivots: Vec<usize>,
+}
+
+/// Run single source part of Brandes' algorithm from each pivot.
+fn accumulate_from_pivots(adjacency: &[Vec<usize>], pivots: &[usize]) -> PivotSums {
+    let nodes = adjacency.len();
+    let mut sums = PivotSums {
+        dependencies: vec![0.0; nodes],
+        distance_sums: vec![0.0; nodes],
+        reached_pivots: vec![0; nodes],
+    };
+
+    let mut distance = vec![usize::MAX; nodes];
+    let mut paths = vec![0.0; nodes];
+    let mut dependency = vec![0.0; nodes];
+    let mut order = Vec::with_capacity(nodes);
+    let mut queue = VecDeque::new();
+
+    for &pivot in pivots {
+        distance.fill(usize::MAX);
+        paths.fill(0.0);
+        dependency.fill(0.0);
+        order.clear();
+
+        distance[pivot] = 0;
+        paths[pivot] = 1.0;
+        queue.push_back(pivot);
+
+        while let Some(node) = queue.pop_front() {
+            order.push(node);
+            for &peer in &adjacency[node] {
+                if distance[peer] == usize::MAX {
+                    distance[peer] = distance[node] + 1;
+                    queue.push_back(peer);
+                }
+                if distance[peer] == distance[node] + 1 {
+                    paths[peer] += paths[node];
+                }
+            }
+        }
+
+        // Nodes are visited in order of non-increasing distance, so dependencies of all
+        // successors are known when the node is processed.
+        for &node in order.iter().rev() {
+            for &peer in &adjacency[node] {
+                if distance[peer] != usize::MAX && distance[peer] + 1 == distance[node] {
+                    dependency[peer] += paths[peer] / paths[node] * (1.0 + dependency[node]);
+                }
+            }
+
+            if node != pivot {
+                sums.dependencies[node] += dependency[node];
+                sums.distance_sums[node] += distance[node] as f64;
+                sums.reached_pivots[node] += 1;
+            }
+        }
+    }
+
+    sums
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn approximation_parameters_test() {
+        let mut config = AnalysisConfiguration::default();
+        assert_eq!(CentralityApproximation::from_config(&config, 100), None);
+
+        config.centrality_mode = CentralityMode::Approximate;
+        config.error_bound = Some(0.5);
+        let approximation = CentralityApproximation::from_config(&config, 100).unwrap();
+        assert_eq!(approximation.pivots, 19);
+        assert_eq!(approximation.error_bound, Some(0.5));
+
+        // Sample size takes precedence and is limited to the number of nodes.
+        config.sample_size = Some(1000);
+        let approximation = CentralityApproximation::from_config(&config, 100).unwrap();
+        assert_eq!(approximation.pivots, 100);
+        assert_eq!(approximation.error_bound, None);
+    }
+
+    #[test]
+    fn approximate_centralities_test() {
+        // Path 0 - 1 - 2 - 3 and isolated node 4.
+        let adjacency = vec![vec![1], vec![0, 2], vec![1, 3], vec![2], vec![]];
+        let approximation = CentralityApproximation {
+            pivots: 5,
+            nodes: 5,
+            error_bound: None,
+            seed: 7,
+        };
+
+        // With all nodes used as pivots the result is exact.
+        let (betweenness, closeness) = approximate_centralities(&adjacency, &approximation, 2);
+        assert_eq!(betweenness, vec![0.0, 2.0, 2.0, 0.0, 0.0]);
+        assert_eq!(closeness, vec![2.0, 4.0 / 3.0, 4.0 / 3.0, 2.0, 0.0]);
+
+        // The same seed gives the same pivots.
+        let approximation = CentralityApproximation {
+            pivots: 2,
+            ..approximation
+        };
+        assert_eq!(
+            approximate_centralities(&adjacency, &approximation, 1),
+            approximate_centralities(&adjacency, &approximation, 3)
+        );
+    }
+
+    #[test]
+    fn pagerank_test() {
+        // Star with center 0 and isolated node 4.
+        let adjacency = vec![vec![1, 2, 3], vec![0], vec![0], vec![0], vec![]];
+        let rank = pagerank(&adjacency, 0.85);
+
+        assert!((rank.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+        assert!(rank[0] > rank[1]);
+        assert!((rank[1] - rank[2]).abs() < 1e-12);
+        assert!(rank[4] < rank[1]);
+    }
+}
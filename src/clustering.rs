@@ -0,0 +1,127 @@
+//! Triangle counting based clustering metrics.
+//!
+//! Local clustering coefficient tells how close the node's neighbourhood is to a clique, while
+//! transitivity and average clustering describe the whole network.
+
+use serde::{Deserialize, Serialize};
+
+/// Network wide clustering figures.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ClusteringSummary {
+    /// Ratio of closed triplets to all connected triplets (global clustering coefficient)
+    pub transitivity: f64,
+    /// Mean of the local clustering coefficients of all nodes
+    pub average_clustering: f64,
+}
+
+/// Compute local clustering coefficient of every node of the undirected graph given as an
+/// adjacency list (sorted, without duplicates) together with the network wide figures.
+pub fn compute_clustering(adjacency: &[Vec<usize>]) -> (Vec<f64>, ClusteringSummary) {
+    let triangles = count_triangles(adjacency);
+
+    let mut closed_triplets = 0.0;
+    let mut connected_triplets = 0.0;
+    let coefficients = adjacency
+        .iter()
+        .zip(&triangles)
+        .map(|(peers, triangles)| {
+            let pairs = (peers.len() * peers.len().saturating_sub(1) / 2) as f64;
+            closed_triplets += *triangles as f64;
+            connected_triplets += pairs;
+
+            if pairs > 0.0 {
+                *triangles as f64 / pairs
+            } else {
+                0.0
+            }
+        })
+        .collect::<Vec<f64>>();
+
+    let summary = ClusteringSummary {
+        transitivity: if connected_triplets > 0.0 {
+            closed_triplets / connected_triplets
+        } else {
+            0.0
+        },
+        average_clustering: if coefficients.is_empty() {
+            0.0
+        } else {
+            coefficients.iter().sum::<f64>() / coefficients.len() as f64
+        },
+    };
+
+    (coefficients, summary)
+}
+
+/// Count triangles each node belongs to.
+/// Each connection is oriented from the node with lower degree to the one with higher degree
+/// (ties broken by index), so every triangle is found exactly once and hubs don't need to check
+/// all pairs of their neighbours.
+fn count_triangles(adjacency: &[Vec<usize>]) -> Vec<usize> {
+    let rank = |node: usize| (adjacency[node].len(), node);
+
+    let forward = adjacency
+        .iter()
+        .enumerate()
+        .map(|(node, peers)| {
+            peers
+                .iter()
+                .copied()
+                .filter(|peer| rank(*peer) > rank(node))
+                .collect::<Vec<usize>>()
+        })
+        .collect::<Vec<_>>();
+
+    let mut triangles = vec![0; adjacency.len()];
+    for (node, peers) in forward.iter().enumerate() {
+        for &peer in peers {
+            // Both lists are sorted, so common nodes can be found by merging them.
+            let (mut i, mut j) = (0, 0);
+            let peer_peers = &forward[peer];
+            while i < peers.len() && j < peer_peers.len() {
+                match peers[i].cmp(&peer_peers[j]) {
+                    std::cmp::Ordering::Less => i += 1,
+                    std::cmp::Ordering::Greater => j += 1,
+                    std::cmp::Ordering::Equal => {
+                        triangles[node] += 1;
+                        triangles[peer] += 1;
+                        triangles[peers[i]] += 1;
+                        i += 1;
+                        j += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    triangles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compute_clustering_test() {
+        // Triangle 0, 1, 2 with node 3 hanging on node 2 and isolated node 4.
+        let adjacency = vec![vec![1, 2], vec![0, 2], vec![0, 1, 3], vec![2], vec![]];
+        let (coefficients, summary) = compute_clustering(&adjacency);
+
+        assert_eq!(coefficients, vec![1.0, 1.0, 1.0 / 3.0, 0.0, 0.0]);
+        // 3 closed triplets (one per triangle corner) of 5 connected ones.
+        assert!((summary.transitivity - 3.0 / 5.0).abs() < 1e-12);
+        assert!((summary.average_clustering - (7.0 / 3.0) / 5.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn compute_clustering_clique_test() {
+        let adjacency = (0..5)
+            .map(|node| (0..5).filter(|peer| *peer != node).collect())
+            .collect::<Vec<Vec<usize>>>();
+        let (coefficients, summary) = compute_clustering(&adjacency);
+
+        assert!(coefficients.iter().all(|c| (*c - 1.0).abs() < 1e-12));
+        assert!((summary.transitivity - 1.0).abs() < 1e-12);
+        assert!((summary.average_clustering - 1.0).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,302 @@
+//! Community detection.
+//!
+//! Communities are groups of nodes with many more connections inside of the group than to the
+//! rest of the network - e.g. nodes run by the same hosting provider or the same client
+//! implementation. Networks split along such lines easily, so it's worth knowing them.
+
+use std::collections::HashMap;
+
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::config::CommunityAlgorithm;
+
+/// Maximum number of label propagation rounds.
+const MAX_PROPAGATION_ROUNDS: usize = 100;
+
+/// Network wide community figures.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CommunitySummary {
+    /// Modularity of the found partition - the higher, the more distinct the communities are
+    pub modularity: f64,
+    /// Number of nodes in each community, biggest first (index is the community id)
+    pub sizes: Vec<usize>,
+}
+
+/// Find communities of the undirected graph given as an adjacency list (without duplicates)
+/// and return community id of every node. Communities are ordered by size, so 0 is the biggest
+/// one. Seed is used to randomize the order nodes are visited in.
+pub fn detect_communities(
+    adjacency: &[Vec<usize>],
+    algorithm: CommunityAlgorithm,
+    seed: u64,
+) -> (Vec<usize>, CommunitySummary) {
+    let communities = match algorithm {
+        CommunityAlgorithm::Louvain => louvain(adjacency, seed),
+        CommunityAlgorithm::LabelPropagation => label_propagation(adjacency, seed),
+    };
+    let (communities, sizes) = order_by_size(&communities);
+
+    let summary = CommunitySummary {
+        modularity: modularity(adjacency, &communities),
+        sizes,
+    };
+
+    (communities, summary)
+}
+
+/// Compute modularity of the partition: share of the connections inside communities minus the
+/// share expected if connections were random (with node degrees preserved).
+pub fn modularity(adjacency: &[Vec<usize>], communities: &[usize]) -> f64 {
+    let doubled_connections = adjacency.iter().map(|peers| peers.len()).sum::<usize>() as f64;
+    if doubled_connections == 0.0 {
+        return 0.0;
+    }
+
+    let community_count = communities.iter().max().map_or(0, |max| max + 1);
+    let mut internal = vec![0.0; community_count];
+    let mut total_degree = vec![0.0; community_count];
+    for (node, peers) in adjacency.iter().enumerate() {
+        let community = communities[node];
+        total_degree[community] += peers.len() as f64;
+        internal[community] += peers
+            .iter()
+            .filter(|peer| communities[**peer] == community)
+            .count() as f64;
+    }
+
+    internal
+        .iter()
+        .zip(&total_degree)
+        .map(|(internal, total)| {
+            internal / doubled_connections - (total / doubled_connections).powi(2)
+        })
+        .sum()
+}
+
+/// Louvain method - nodes are moved to the neighbouring community giving the highest
+/// modularity gain and when nothing moves anymore, communities are merged into single nodes
+/// and the process is repeated on the smaller graph.
+fn louvain(adjacency: &[Vec<usize>], seed: u64) -> Vec<usize> {
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    // Weighted graph of the current level (self loops are not kept, only degrees matter).
+    let mut neighbours = adjacency
+        .iter()
+        .map(|peers| peers.iter().map(|&peer| (peer, 1.0)).collect::<Vec<_>>())
+        .collect::<Vec<Vec<(usize, f64)>>>();
+    let mut degrees = adjacency
+        .iter()
+        .map(|peers| peers.len() as f64)
+        .collect::<Vec<f64>>();
+    let doubled_weight = degrees.iter().sum::<f64>();
+
+    // Community of every original node.
+    let mut membership = (0..adjacency.len()).collect::<Vec<usize>>();
+    if doubled_weight == 0.0 {
+        return membership;
+    }
+
+    loop {
+        let level_nodes = neighbours.len();
+        let mut community = (0..level_nodes).collect::<Vec<usize>>();
+        let mut community_degree = degrees.clone();
+        let mut order = (0..level_nodes).collect::<Vec<usize>>();
+        order.shuffle(&mut rng);
+
+        let mut moved_at_level = false;
+        loop {
+            let mut moved = false;
+            for &node in &order {
+                let current = community[node];
+                let degree = degrees[node];
+
+                let mut links = HashMap::new();
+                for &(peer, weight) in &neighbours[node] {
+                    *links.entry(community[peer]).or_insert(0.0) += weight;
+                }
+
+                community_degree[current] -= degree;
+                let gain = |target: usize, links_to_target: f64| {
+                    links_to_target - community_degree[target] * degree / doubled_weight
+                };
+
+                let mut best = current;
+                let mut best_gain = gain(current, links.get(&current).copied().unwrap_or(0.0));
+                let mut candidates = links.into_iter().collect::<Vec<_>>();
+                candidates.sort_unstable_by_key(|(target, _)| *target);
+                for (target, links_to_target) in candidates {
+                    let target_gain = gain(target, links_to_target);
+                    if target_gain > best_gain + 1e-12 {
+                        best = target;
+                        best_gain = target_gain;
+                    }
+                }
+
+                community_degree[best] += degree;
+                if best != current {
+                    community[node] = best;
+                    moved = true;
+                }
+            }
+
+            if !moved {
+                break;
+            }
+            moved_at_level = true;
+        }
+
+        if !moved_at_level {
+            break;
+        }
+
+        // Renumber communities and merge each of them into a single node.
+        let (community, _) = order_by_size(&community);
+        let community_count = community.iter().max().map_or(0, |max| max + 1);
+
+        let mut merged = vec![HashMap::new(); community_count];
+        let mut merged_degrees = vec![0.0; community_count];
+        for (node, peers) in neighbours.iter().enumerate() {
+            let node_community = community[node];
+            merged_degrees[node_community] += degrees[node];
+            for &(peer, weight) in peers {
+                if community[peer] != node_community {
+                    *merged[node_community].entry(community[peer]).or_insert(0.0) += weight;
+                }
+            }
+        }
+
+        neighbours = merged
+            .into_iter()
+            .map(|links| {
+                let mut links = links.into_iter().collect::<Vec<(usize, f64)>>();
+                links.sort_unstable_by_key(|(peer, _)| *peer);
+                links
+            })
+            .collect();
+        degrees = merged_degrees;
+        for node_community in membership.iter_mut() {
+            *node_community = community[*node_community];
+        }
+    }
+
+    membership
+}
+
+/// Label propagation - every node takes the label most common among its neighbours until labels
+/// stop changing. Ties are resolved in favour of the current label, then the lowest one.
+fn label_propagation(adjacency: &[Vec<usize>], seed: u64) -> Vec<usize> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut labels = (0..adjacency.len()).collect::<Vec<usize>>();
+    let mut order = labels.clone();
+
+    for _ in 0..MAX_PROPAGATION_ROUNDS {
+        order.shuffle(&mut rng);
+
+        let mut changed = false;
+        for &node in &order {
+            if adjacency[node].is_empty() {
+                continue;
+            }
+
+            let mut counts = HashMap::new();
+            for &peer in &adjacency[node] {
+                *counts.entry(labels[peer]).or_insert(0) += 1;
+            }
+
+            let max_count = counts.values().copied().max().unwrap_or_default();
+            if counts.get(&labels[node]) == Some(&max_count) {
+                continue;
+            }
+
+            labels[node] = counts
+                .into_iter()
+                .filter(|(_, count)| *count == max_count)
+                .map(|(label, _)| label)
+                .min()
+                .expect("node has at least one neighbour");
+            changed = true;
+        }
+
+        if !changed {
+            break;
+        }
+    }
+
+    labels
+}
+
+/// Renumber communities so the biggest one gets id 0. Communities of the same size are ordered
+/// by their lowest node index. Returns new ids and community sizes.
+fn order_by_size(communities: &[usize]) -> (Vec<usize>, Vec<usize>) {
+    let mut sizes: HashMap<usize, (usize, usize)> = HashMap::new();
+    for (node, community) in communities.iter().enumerate() {
+        sizes.entry(*community).or_insert((0, node)).0 += 1;
+    }
+
+    let mut ordered = sizes.into_iter().collect::<Vec<_>>();
+    ordered.sort_unstable_by_key(|(_, (size, first_node))| (std::cmp::Reverse(*size), *first_node));
+
+    let new_ids = ordered
+        .iter()
+        .enumerate()
+        .map(|(id, (community, _))| (*community, id))
+        .collect::<HashMap<usize, usize>>();
+
+    (
+        communities.iter().map(|c| new_ids[c]).collect(),
+        ordered.iter().map(|(_, (size, _))| *size).collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two cliques of 5 nodes (0-4 and 5-9) joined by a single connection 4 - 5, plus isolated
+    /// node 10.
+    fn two_cliques() -> Vec<Vec<usize>> {
+        let mut adjacency = vec![Vec::new(); 11];
+        for group in [0..5, 5..10] {
+            for a in group.clone() {
+                for b in group.clone() {
+                    if a != b {
+                        adjacency[a].push(b);
+                    }
+                }
+            }
+        }
+        adjacency[4].push(5);
+        adjacency[5].push(4);
+        adjacency
+    }
+
+    #[test]
+    fn detect_communities_test() {
+        let adjacency = two_cliques();
+
+        for algorithm in [
+            CommunityAlgorithm::Louvain,
+            CommunityAlgorithm::LabelPropagation,
+        ] {
+            let (communities, summary) = detect_communities(&adjacency, algorithm, 1);
+
+            assert_eq!(summary.sizes, vec![5, 5, 1]);
+            assert!(communities[..5].iter().all(|c| *c == communities[0]));
+            assert!(communities[5..10].iter().all(|c| *c == communities[5]));
+            assert_ne!(communities[0], communities[5]);
+            assert_eq!(communities[10], 2);
+            assert!((summary.modularity - modularity(&adjacency, &communities)).abs() < 1e-12);
+            assert!(summary.modularity > 0.4);
+        }
+    }
+
+    #[test]
+    fn modularity_test() {
+        let adjacency = two_cliques();
+
+        // All nodes in one community give no modularity.
+        assert!(modularity(&adjacency, &[0; 11]).abs() < 1e-12);
+        assert_eq!(modularity(&[vec![], vec![]], &[0, 1]), 0.0);
+    }
+}
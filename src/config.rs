@@ -1,14 +1,26 @@
 use std::{fs, path::PathBuf, thread};
 
-use anyhow::Result;
 use serde::Deserialize;
 use ziggurat_core_crawler::summary::NetworkType;
 
-use crate::ips::config::IPSConfiguration;
+use crate::{
+    error::{CrunchyError, Result},
+    ips::config::IPSConfiguration,
+    percolation::RemovalStrategy,
+};
 
 /// Default number of days to keep each entry in cache
 pub const DEFAULT_KEEP_IN_CACHE_DAYS: u16 = 14;
 
+/// Default PageRank damping factor
+pub const DEFAULT_PAGERANK_DAMPING: f64 = 0.85;
+
+/// Default share of the nodes removed in a single percolation step
+pub const DEFAULT_PERCOLATION_STEP_PERCENTAGE: f64 = 0.01;
+
+/// Default share of the nodes removed before percolation stops
+pub const DEFAULT_PERCOLATION_MAX_REMOVED_PERCENTAGE: f64 = 0.5;
+
 /// Main configuration structure
 #[derive(Debug, Clone, Deserialize)]
 pub struct CrunchyConfiguration {
@@ -24,6 +36,53 @@ pub struct CrunchyConfiguration {
     pub network_type_filter: Option<NetworkType>,
     /// Number of threads to use
     pub num_threads: usize,
+    /// Configuration of the graph metrics computation
+    #[serde(default)]
+    pub analysis_config: AnalysisConfiguration,
+    /// Configuration of the percolation simulator
+    #[serde(default)]
+    pub percolation_config: PercolationConfiguration,
+}
+
+/// Configuration of the graph metrics computation
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AnalysisConfiguration {
+    /// Indicates if betweenness and closeness should be computed exactly or approximated
+    pub centrality_mode: CentralityMode,
+    /// Number of pivot nodes used for approximation (takes precedence over the error bound)
+    pub sample_size: Option<usize>,
+    /// Expected approximation error used to derive number of pivot nodes
+    pub error_bound: Option<f64>,
+    /// Seed for random choices, so results can be reproduced
+    pub seed: u64,
+    /// Probability of following a connection (instead of jumping to a random node) in PageRank
+    pub pagerank_damping: f64,
+    /// Minimal core number of the network core nodes (if none, only the main core is used)
+    pub core_threshold: Option<usize>,
+    /// Number of nodes hop distances are measured from (if none, all nodes are used)
+    pub distance_sample_size: Option<usize>,
+    /// Algorithm used to find communities of the network
+    pub community_algorithm: CommunityAlgorithm,
+}
+
+/// Configuration of the percolation simulator
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PercolationConfiguration {
+    /// Strategies used to choose removed nodes, each gives a separate curve (betweenness is
+    /// recomputed after every step, so that strategy has to be requested explicitly)
+    pub strategies: Vec<RemovalStrategy>,
+    /// Share of the nodes removed in a single step (country and subnet strategies remove whole
+    /// groups instead)
+    pub step_percentage: f64,
+    /// Share of the nodes removed before the simulation stops (group strategies stop before a
+    /// group which would exceed it)
+    pub max_removed_percentage: f64,
+    /// Peer list files the networks are built from (if empty, the state network is used)
+    pub peer_file_paths: Vec<PathBuf>,
+    /// Path where curves are written - CSV if the extension is `csv`, JSON otherwise
+    pub output_file_path: Option<PathBuf>,
 }
 
 /// Configuration for GeoIP module
@@ -58,14 +117,105 @@ pub enum GeoLocationMode {
     PreferDistant,
 }
 
+/// CentralityMode enum - indicates how betweenness and closeness centralities are computed.
+#[derive(Debug, Default, PartialEq, Clone, Copy, Deserialize)]
+pub enum CentralityMode {
+    /// All pairs shortest paths - precise but slow for big networks
+    #[default]
+    Exact,
+    /// Shortest paths from randomly chosen pivot nodes only
+    Approximate,
+}
+
+/// CommunityAlgorithm enum - indicates how communities of the network are found.
+#[derive(Debug, Default, PartialEq, Clone, Copy, Deserialize)]
+pub enum CommunityAlgorithm {
+    /// Modularity optimization by moving nodes between communities and merging them
+    #[default]
+    Louvain,
+    /// Nodes take the label most common among their neighbours - fast but less stable
+    LabelPropagation,
+}
+
 impl CrunchyConfiguration {
     pub fn new(conf_path: &str) -> Result<CrunchyConfiguration> {
-        let config_string = fs::read_to_string(conf_path)?;
-        let crunchy_config: CrunchyConfiguration = toml::from_str(&config_string)?;
+        let config_string = fs::read_to_string(conf_path).map_err(|e| {
+            CrunchyError::io(format!("could not open configuration file {conf_path}"), e)
+        })?;
+        let crunchy_config: CrunchyConfiguration = toml::from_str(&config_string).map_err(|e| {
+            CrunchyError::parse(format!("could not parse configuration file {conf_path}"), e)
+        })?;
+        crunchy_config.analysis_config.validate()?;
+        crunchy_config.ips_config.validate()?;
+        crunchy_config.percolation_config.validate()?;
         Ok(crunchy_config)
     }
 }
 
+impl AnalysisConfiguration {
+    /// Check if values are in allowed ranges.
+    pub fn validate(&self) -> Result<()> {
+        if self.sample_size == Some(0) {
+            return Err(CrunchyError::Config(
+                "sample_size must be greater than 0".to_owned(),
+            ));
+        }
+
+        if self.distance_sample_size == Some(0) {
+            return Err(CrunchyError::Config(
+                "distance_sample_size must be greater than 0".to_owned(),
+            ));
+        }
+
+        if let Some(error_bound) = self.error_bound {
+            if error_bound <= 0.0 || error_bound >= 1.0 {
+                return Err(CrunchyError::Config(format!(
+                    "error_bound must be between 0 and 1, got {error_bound}"
+                )));
+            }
+        }
+
+        if self.pagerank_damping <= 0.0 || self.pagerank_damping >= 1.0 {
+            return Err(CrunchyError::Config(format!(
+                "pagerank_damping must be between 0 and 1, got {}",
+                self.pagerank_damping
+            )));
+        }
+
+        if self.centrality_mode == CentralityMode::Approximate
+            && self.sample_size.is_none()
+            && self.error_bound.is_none()
+        {
+            return Err(CrunchyError::Config(
+                "approximate centrality mode requires sample_size or error_bound".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl PercolationConfiguration {
+    /// Check if values are in allowed ranges.
+    pub fn validate(&self) -> Result<()> {
+        if self.step_percentage <= 0.0 || self.step_percentage > 1.0 {
+            return Err(CrunchyError::Config(format!(
+                "step_percentage must be between 0 (exclusive) and 1, got {}",
+                self.step_percentage
+            )));
+        }
+
+        if self.max_removed_percentage <= 0.0 || self.max_removed_percentage > 1.0 {
+            return Err(CrunchyError::Config(format!(
+                "max_removed_percentage must be between 0 (exclusive) and 1, got {}",
+                self.max_removed_percentage
+            )));
+        }
+
+        Ok(())
+    }
+}
+
 impl Default for CrunchyConfiguration {
     fn default() -> CrunchyConfiguration {
         CrunchyConfiguration {
@@ -75,6 +225,40 @@ impl Default for CrunchyConfiguration {
             geoip_config: GeoIPConfiguration::default(),
             network_type_filter: None,
             num_threads: thread::available_parallelism().unwrap().get(),
+            analysis_config: AnalysisConfiguration::default(),
+            percolation_config: PercolationConfiguration::default(),
+        }
+    }
+}
+
+impl Default for AnalysisConfiguration {
+    fn default() -> AnalysisConfiguration {
+        AnalysisConfiguration {
+            centrality_mode: CentralityMode::Exact,
+            sample_size: None,
+            error_bound: None,
+            seed: 0,
+            pagerank_damping: DEFAULT_PAGERANK_DAMPING,
+            core_threshold: None,
+            distance_sample_size: None,
+            community_algorithm: CommunityAlgorithm::Louvain,
+        }
+    }
+}
+
+impl Default for PercolationConfiguration {
+    fn default() -> PercolationConfiguration {
+        PercolationConfiguration {
+            strategies: vec![
+                RemovalStrategy::Random,
+                RemovalStrategy::HighestDegree,
+                RemovalStrategy::Country,
+                RemovalStrategy::Subnet,
+            ],
+            step_percentage: DEFAULT_PERCOLATION_STEP_PERCENTAGE,
+            max_removed_percentage: DEFAULT_PERCOLATION_MAX_REMOVED_PERCENTAGE,
+            peer_file_paths: Vec::new(),
+            output_file_path: None,
         }
     }
 }
@@ -0,0 +1,182 @@
+//! Hop distance metrics.
+//!
+//! Number of hops between nodes is what determines block and transaction propagation time.
+//! Distances are found with breadth-first searches - either from all nodes or, for big graphs,
+//! from a random sample of them. In the latter case eccentricities (and so the diameter and
+//! radius) are lower bounds: the longest distance to any of the sampled nodes.
+
+use std::{collections::VecDeque, thread};
+
+use rand::{rngs::StdRng, seq::index, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+/// Network wide distance figures. Only pairs of nodes connected by some path are taken into
+/// account.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DistanceSummary {
+    /// The longest shortest path between any two nodes
+    pub diameter: usize,
+    /// The lowest eccentricity of the nodes having any connection
+    pub radius: usize,
+    /// Mean number of hops between two nodes
+    pub average_path_length: f64,
+    /// Number of (source, target) pairs for each number of hops (index is the number of hops)
+    pub hop_distribution: Vec<usize>,
+    /// Number of nodes searches were run from
+    pub sources: usize,
+}
+
+/// Compute eccentricity of every node of the undirected graph given as an adjacency list and
+/// the network wide distance figures. If sample size is given (and it's lower than number of
+/// nodes), distances are measured only from that many randomly chosen nodes.
+pub fn compute_distances(
+    adjacency: &[Vec<usize>],
+    sample_size: Option<usize>,
+    seed: u64,
+    num_threads: usize,
+) -> (Vec<usize>, DistanceSummary) {
+    let nodes = adjacency.len();
+    if nodes == 0 {
+        return (Vec::new(), DistanceSummary::default());
+    }
+
+    let sources = match sample_size {
+        Some(sample_size) if sample_size < nodes => {
+            let mut rng = StdRng::seed_from_u64(seed);
+            index::sample(&mut rng, nodes, sample_size.max(1)).into_vec()
+        }
+        _ => (0..nodes).collect(),
+    };
+
+    let chunk_size = sources.len().div_ceil(num_threads.max(1));
+    let partials = thread::scope(|scope| {
+        let handles = sources
+            .chunks(chunk_size)
+            .map(|chunk| scope.spawn(|| search_from_sources(adjacency, chunk)))
+            .collect::<Vec<_>>();
+
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("distance worker panicked"))
+            .collect::<Vec<_>>()
+    });
+
+    let mut eccentricities = vec![0; nodes];
+    let mut hop_distribution = Vec::new();
+    for partial in partials {
+        for (eccentricity, partial_eccentricity) in
+            eccentricities.iter_mut().zip(partial.eccentricities)
+        {
+            *eccentricity = (*eccentricity).max(partial_eccentricity);
+        }
+
+        if hop_distribution.len() < partial.hop_distribution.len() {
+            hop_distribution.resize(partial.hop_distribution.len(), 0);
+        }
+        for (count, partial_count) in hop_distribution.iter_mut().zip(partial.hop_distribution) {
+            *count += partial_count;
+        }
+    }
+
+    let (hops, pairs) = hop_distribution
+        .iter()
+        .enumerate()
+        .fold((0, 0), |(hops, pairs), (distance, count)| {
+            (hops + distance * count, pairs + count)
+        });
+
+    let summary = DistanceSummary {
+        diameter: eccentricities.iter().copied().max().unwrap_or_default(),
+        radius: eccentricities
+            .iter()
+            .zip(adjacency)
+            .filter(|(_, peers)| !peers.is_empty())
+            .map(|(eccentricity, _)| *eccentricity)
+            .min()
+            .unwrap_or_default(),
+        average_path_length: if pairs > 0 {
+            hops as f64 / pairs as f64
+        } else {
+            0.0
+        },
+        hop_distribution,
+        sources: sources.len(),
+    };
+
+    (eccentricities, summary)
+}
+
+/// Partial results of the searches from some sources.
+struct SourceDistances {
+    /// The longest distance found for each node
+    eccentricities: Vec<usize>,
+    /// Number of reached nodes for each number of hops
+    hop_distribution: Vec<usize>,
+}
+
+/// Run breadth-first search from each source.
+fn search_from_sources(adjacency: &[Vec<usize>], sources: &[usize]) -> SourceDistances {
+    let nodes = adjacency.len();
+    let mut result = SourceDistances {
+        eccentricities: vec![0; nodes],
+        hop_distribution: vec![0],
+    };
+
+    let mut distance = vec![usize::MAX; nodes];
+    let mut queue = VecDeque::new();
+    for &source in sources {
+        distance.fill(usize::MAX);
+        distance[source] = 0;
+        queue.push_back(source);
+
+        while let Some(node) = queue.pop_front() {
+            let hops = distance[node];
+
+            // Graph is undirected, so distance counts for both ends.
+            result.eccentricities[node] = result.eccentricities[node].max(hops);
+            result.eccentricities[source] = result.eccentricities[source].max(hops);
+            if hops > 0 {
+                if result.hop_distribution.len() <= hops {
+                    result.hop_distribution.resize(hops + 1, 0);
+                }
+                result.hop_distribution[hops] += 1;
+            }
+
+            for &peer in &adjacency[node] {
+                if distance[peer] == usize::MAX {
+                    distance[peer] = hops + 1;
+                    queue.push_back(peer);
+                }
+            }
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compute_distances_test() {
+        // Path 0 - 1 - 2 - 3 and isolated node 4.
+        let adjacency = vec![vec![1], vec![0, 2], vec![1, 3], vec![2], vec![]];
+        let (eccentricities, summary) = compute_distances(&adjacency, None, 0, 2);
+
+        assert_eq!(eccentricities, vec![3, 2, 2, 3, 0]);
+        assert_eq!(summary.diameter, 3);
+        assert_eq!(summary.radius, 2);
+        assert_eq!(summary.hop_distribution, vec![0, 6, 4, 2]);
+        assert!((summary.average_path_length - 20.0 / 12.0).abs() < 1e-12);
+        assert_eq!(summary.sources, 5);
+
+        // Sampled eccentricities are lower bounds of the exact ones.
+        let (sampled, summary) = compute_distances(&adjacency, Some(2), 1, 1);
+        assert_eq!(summary.sources, 2);
+        assert!(sampled
+            .iter()
+            .zip(&eccentricities)
+            .all(|(sampled, exact)| sampled <= exact));
+    }
+}
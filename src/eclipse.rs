@@ -0,0 +1,497 @@
+//! Eclipse risk.
+//!
+//! A node is eclipsed when all of its peers are controlled by an attacker. That's much easier
+//! if the peers come from a single subnet or country, if they all hang on a single hub, or if
+//! only a few nodes stand between the node and the rest of the network. Each of these factors
+//! is scored between 0 (safe) and 1 (risky) and the eclipse risk of the node is their mean.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    thread,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{nodes::subnet, Node};
+
+/// Number of node-disjoint paths to the network core above which a node is considered safe.
+const SAFE_CONNECTIVITY: usize = 4;
+
+/// Nodes with degree above this percentile of all degrees are considered hubs.
+const HUB_DEGREE_PERCENTILE: usize = 90;
+
+/// Eclipse risk at or above this value is considered high.
+pub const HIGH_ECLIPSE_RISK: f64 = 0.5;
+
+/// Number of the most endangered nodes listed in the summary.
+const MOST_AT_RISK_COUNT: usize = 10;
+
+/// Factors of the eclipse risk of a single node, each between 0 (safe) and 1 (risky)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct EclipseFactors {
+    /// Share of the peers in the most common IPv4 /16 or IPv6 /32 subnet
+    pub subnet_concentration: f64,
+    /// Share of the peers in the most common country (peers without known country don't count)
+    pub country_concentration: f64,
+    /// Share of the peers which are, or are connected to, the same hub (a node with degree
+    /// above `HUB_DEGREE_PERCENTILE` of all degrees)
+    pub hub_dependence: f64,
+    /// Node-disjoint paths to the core of the giant component, scaled down so that
+    /// `SAFE_CONNECTIVITY` or more paths give 0
+    pub connectivity_deficit: f64,
+}
+
+impl EclipseFactors {
+    /// Eclipse risk - mean of the factors.
+    pub fn risk(&self) -> f64 {
+        (self.subnet_concentration
+            + self.country_concentration
+            + self.hub_dependence
+            + self.connectivity_deficit)
+            / 4.0
+    }
+}
+
+/// Network wide eclipse risk figures
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EclipseSummary {
+    /// Mean eclipse risk of the nodes
+    pub average_risk: f64,
+    /// Number of nodes with eclipse risk at least `HIGH_ECLIPSE_RISK`
+    pub high_risk_nodes: usize,
+    /// Mean of every factor over all nodes
+    pub average_factors: EclipseFactors,
+    /// Indices of the most endangered nodes, highest risk first
+    pub most_at_risk: Vec<usize>,
+}
+
+/// Compute eclipse risk factors of every node of the undirected graph given as an adjacency list
+/// (without duplicates) and the network wide summary. Core numbers are used to find the core of
+/// the giant component which node-disjoint paths are counted to.
+pub fn compute_eclipse_risk(
+    nodes: &[Node],
+    adjacency: &[Vec<usize>],
+    core_numbers: &[usize],
+    num_threads: usize,
+) -> (Vec<EclipseFactors>, EclipseSummary) {
+    if nodes.is_empty() {
+        return (Vec::new(), EclipseSummary::default());
+    }
+
+    let connectivity = core_connectivity(adjacency, core_numbers, num_threads);
+    let hubs = HubPeers::new(adjacency);
+    let mut hub_counts = vec![0; nodes.len()];
+
+    let factors = (0..nodes.len())
+        .map(|idx| {
+            let peers = &adjacency[idx];
+            if peers.is_empty() {
+                return EclipseFactors {
+                    subnet_concentration: 1.0,
+                    country_concentration: 1.0,
+                    hub_dependence: 1.0,
+                    connectivity_deficit: 1.0,
+                };
+            }
+
+            let subnet_concentration = largest_share(
+                peers.len(),
+                peers
+                    .iter()
+                    .map(|peer| Some(subnet(nodes[*peer].addr.ip()))),
+            );
+            let country_concentration = largest_share(
+                peers.len(),
+                peers.iter().map(|peer| {
+                    nodes[*peer]
+                        .geolocation
+                        .as_ref()
+                        .and_then(|geolocation| geolocation.country.clone())
+                }),
+            );
+            let hub_dependence = hubs.dependence(idx, peers, &mut hub_counts);
+
+            EclipseFactors {
+                subnet_concentration,
+                country_concentration,
+                hub_dependence,
+                connectivity_deficit: 1.0
+                    - connectivity[idx].min(SAFE_CONNECTIVITY) as f64 / SAFE_CONNECTIVITY as f64,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let count = factors.len() as f64;
+    let risks = factors.iter().map(|f| f.risk()).collect::<Vec<_>>();
+
+    let mut most_at_risk = (0..risks.len()).collect::<Vec<_>>();
+    most_at_risk.sort_by(|a, b| risks[*b].total_cmp(&risks[*a]).then(a.cmp(b)));
+    most_at_risk.truncate(MOST_AT_RISK_COUNT);
+
+    let summary = EclipseSummary {
+        average_risk: risks.iter().sum::<f64>() / count,
+        high_risk_nodes: risks
+            .iter()
+            .filter(|risk| **risk >= HIGH_ECLIPSE_RISK)
+            .count(),
+        average_factors: EclipseFactors {
+            subnet_concentration: factors.iter().map(|f| f.subnet_concentration).sum::<f64>()
+                / count,
+            country_concentration: factors.iter().map(|f| f.country_concentration).sum::<f64>()
+                / count,
+            hub_dependence: factors.iter().map(|f| f.hub_dependence).sum::<f64>() / count,
+            connectivity_deficit: factors.iter().map(|f| f.connectivity_deficit).sum::<f64>()
+                / count,
+        },
+        most_at_risk,
+    };
+
+    (factors, summary)
+}
+
+/// Share of the peers in the most common group. Every peer may belong to any number of groups
+/// (including none).
+fn largest_share<G, I>(peers: usize, groups: impl Iterator<Item = I>) -> f64
+where
+    G: std::hash::Hash + Eq,
+    I: IntoIterator<Item = G>,
+{
+    let mut counts = HashMap::new();
+    for peer_groups in groups {
+        for group in peer_groups {
+            *counts.entry(group).or_insert(0) += 1;
+        }
+    }
+
+    counts.values().copied().max().unwrap_or_default() as f64 / peers as f64
+}
+
+/// Hubs next to every node
+struct HubPeers {
+    /// Whether the node is a hub
+    is_hub: Vec<bool>,
+    /// Peers of every node which are hubs
+    hub_peers: Vec<Vec<usize>>,
+}
+
+impl HubPeers {
+    fn new(adjacency: &[Vec<usize>]) -> Self {
+        let mut degrees = adjacency
+            .iter()
+            .map(|peers| peers.len())
+            .collect::<Vec<usize>>();
+        degrees.sort_unstable();
+        let threshold = degrees
+            .get(degrees.len().saturating_sub(1) * HUB_DEGREE_PERCENTILE / 100)
+            .copied()
+            .unwrap_or_default();
+        let is_hub = adjacency
+            .iter()
+            .map(|peers| peers.len() > threshold)
+            .collect::<Vec<bool>>();
+        let hub_peers = adjacency
+            .iter()
+            .map(|peers| peers.iter().copied().filter(|peer| is_hub[*peer]).collect())
+            .collect();
+
+        HubPeers { is_hub, hub_peers }
+    }
+
+    /// Share of the peers of the node which are, or are connected to, the same hub. The counts
+    /// buffer (one entry per node, all zeros) is reused between nodes.
+    fn dependence(&self, node: usize, peers: &[usize], counts: &mut [usize]) -> f64 {
+        let hubs = || {
+            peers
+                .iter()
+                .flat_map(|peer| {
+                    self.hub_peers[*peer]
+                        .iter()
+                        .chain(self.is_hub[*peer].then_some(peer))
+                })
+                .filter(move |hub| **hub != node)
+        };
+
+        let mut largest = 0;
+        for hub in hubs() {
+            counts[*hub] += 1;
+            largest = largest.max(counts[*hub]);
+        }
+        for hub in hubs() {
+            counts[*hub] = 0;
+        }
+
+        largest as f64 / peers.len() as f64
+    }
+}
+
+/// Count node-disjoint paths from every node to the main core of the giant component (nodes
+/// with the highest core number in it), up to `SAFE_CONNECTIVITY`. Nodes outside of the giant
+/// component have no such paths.
+fn core_connectivity(
+    adjacency: &[Vec<usize>],
+    core_numbers: &[usize],
+    num_threads: usize,
+) -> Vec<usize> {
+    let giant = giant_component(adjacency);
+    let max_core = giant
+        .iter()
+        .enumerate()
+        .filter(|(_, in_giant)| **in_giant)
+        .map(|(idx, _)| core_numbers[idx])
+        .max()
+        .unwrap_or_default();
+    let core = (0..adjacency.len())
+        .map(|idx| giant[idx] && core_numbers[idx] == max_core)
+        .collect::<Vec<bool>>();
+    let core_size = core.iter().filter(|in_core| **in_core).count();
+    let network = FlowNetwork::new(adjacency, &core);
+
+    let sources = (0..adjacency.len()).collect::<Vec<usize>>();
+    let chunk_size = sources.len().div_ceil(num_threads.max(1));
+    thread::scope(|scope| {
+        let handles = sources
+            .chunks(chunk_size)
+            .map(|chunk| {
+                let network = &network;
+                let giant = &giant;
+                let core = &core;
+                scope.spawn(move || {
+                    let mut flow = vec![0; network.to.len()];
+                    chunk
+                        .iter()
+                        .map(|source| {
+                            let source = *source;
+                            if !giant[source] {
+                                return 0;
+                            }
+                            // The only core node can't reach the core through other nodes.
+                            if core_size == 1 && core[source] {
+                                return adjacency[source].len().min(SAFE_CONNECTIVITY);
+                            }
+                            network.disjoint_paths(source, core[source], &mut flow)
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("eclipse worker panicked"))
+            .collect()
+    })
+}
+
+/// Mark nodes of the biggest connected component.
+fn giant_component(adjacency: &[Vec<usize>]) -> Vec<bool> {
+    let mut component = vec![usize::MAX; adjacency.len()];
+    let mut sizes = Vec::new();
+    for start in 0..adjacency.len() {
+        if component[start] != usize::MAX {
+            continue;
+        }
+
+        let id = sizes.len();
+        component[start] = id;
+        let mut size = 1;
+        let mut stack = vec![start];
+        while let Some(node) = stack.pop() {
+            for &peer in &adjacency[node] {
+                if component[peer] == usize::MAX {
+                    component[peer] = id;
+                    size += 1;
+                    stack.push(peer);
+                }
+            }
+        }
+        sizes.push(size);
+    }
+
+    // The first of the biggest components.
+    let giant = sizes
+        .iter()
+        .enumerate()
+        .max_by(|a, b| a.1.cmp(b.1).then(b.0.cmp(&a.0)))
+        .map(|(id, _)| id);
+    component.iter().map(|id| Some(*id) == giant).collect()
+}
+
+/// Unit capacity flow network for counting node-disjoint paths. Every node is split into the
+/// `in` (2 * node) and `out` (2 * node + 1) part joined with an edge of capacity 1, so each node
+/// can be passed only once. Core nodes are connected to the sink.
+struct FlowNetwork {
+    /// Outgoing edges of every flow node
+    edges: Vec<Vec<usize>>,
+    /// Target of every edge - each edge is followed by its reverse edge
+    to: Vec<usize>,
+    /// Capacity of every edge
+    capacity: Vec<i32>,
+    /// Index of the sink flow node
+    sink: usize,
+}
+
+impl FlowNetwork {
+    fn new(adjacency: &[Vec<usize>], core: &[bool]) -> Self {
+        let sink = 2 * adjacency.len();
+        let mut network = FlowNetwork {
+            edges: vec![Vec::new(); sink + 1],
+            to: Vec::new(),
+            capacity: Vec::new(),
+            sink,
+        };
+
+        for (node, peers) in adjacency.iter().enumerate() {
+            network.add_edge(2 * node, 2 * node + 1);
+            for &peer in peers {
+                network.add_edge(2 * node + 1, 2 * peer);
+            }
+            if core[node] {
+                network.add_edge(2 * node + 1, sink);
+            }
+        }
+
+        network
+    }
+
+    fn add_edge(&mut self, from: usize, to: usize) {
+        self.edges[from].push(self.to.len());
+        self.to.push(to);
+        self.capacity.push(1);
+        self.edges[to].push(self.to.len());
+        self.to.push(from);
+        self.capacity.push(0);
+    }
+
+    /// Count node-disjoint paths from the source node to the sink, up to `SAFE_CONNECTIVITY`.
+    /// Core source doesn't count as reaching the core by itself. The flow buffer is reused
+    /// between sources.
+    fn disjoint_paths(&self, source: usize, source_in_core: bool, flow: &mut [i32]) -> usize {
+        flow.fill(0);
+        let start = 2 * source + 1;
+        let residual = |edge: usize, flow: &[i32]| {
+            let direct_to_sink = source_in_core && self.to[edge] == self.sink;
+            if direct_to_sink && self.to[edge ^ 1] == start {
+                return 0;
+            }
+            self.capacity[edge] - flow[edge]
+        };
+
+        let mut paths = 0;
+        let mut parent_edge = vec![usize::MAX; self.edges.len()];
+        let mut queue = VecDeque::new();
+        while paths < SAFE_CONNECTIVITY {
+            parent_edge.fill(usize::MAX);
+            queue.clear();
+            queue.push_back(start);
+            let mut reached = false;
+
+            'search: while let Some(flow_node) = queue.pop_front() {
+                for &edge in &self.edges[flow_node] {
+                    let next = self.to[edge];
+                    if next == start || parent_edge[next] != usize::MAX || residual(edge, flow) <= 0
+                    {
+                        continue;
+                    }
+                    parent_edge[next] = edge;
+                    if next == self.sink {
+                        reached = true;
+                        break 'search;
+                    }
+                    queue.push_back(next);
+                }
+            }
+
+            if !reached {
+                break;
+            }
+
+            let mut flow_node = self.sink;
+            while flow_node != start {
+                let edge = parent_edge[flow_node];
+                flow[edge] += 1;
+                flow[edge ^ 1] -= 1;
+                flow_node = self.to[edge ^ 1];
+            }
+            paths += 1;
+        }
+
+        paths
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::*;
+
+    #[test]
+    fn compute_eclipse_risk_test() {
+        // Clique 0 - 3 (all in 10.0.0.0/16), node 4 connected to 0 and 1, node 5 hanging on
+        // node 4 and isolated node 6.
+        let adjacency = vec![
+            vec![1, 2, 3, 4],
+            vec![0, 2, 3, 4],
+            vec![0, 1, 3],
+            vec![0, 1, 2],
+            vec![0, 1, 5],
+            vec![4],
+            vec![],
+        ];
+        let nodes = (0..7)
+            .map(|i| Node {
+                addr: format!("10.{}.0.{i}:8233", if i < 4 { 0 } else { i })
+                    .parse::<SocketAddr>()
+                    .unwrap(),
+                ..Default::default()
+            })
+            .collect::<Vec<Node>>();
+        let core_numbers = vec![3, 3, 3, 3, 2, 1, 0];
+
+        let (factors, summary) = compute_eclipse_risk(&nodes, &adjacency, &core_numbers, 2);
+
+        // Node 5 has a single peer - everything is concentrated on it.
+        assert_eq!(factors[5].subnet_concentration, 1.0);
+        assert_eq!(factors[5].connectivity_deficit, 0.75);
+        // Node 4 reaches the core through 0 and 1, both connected to 2 and 3.
+        assert_eq!(factors[4].subnet_concentration, 2.0 / 3.0);
+        // Degrees are alike, there are no hubs.
+        assert_eq!(factors[4].hub_dependence, 0.0);
+        assert_eq!(factors[4].connectivity_deficit, 0.5);
+        // Core node 2 reaches other core nodes directly.
+        assert_eq!(factors[2].connectivity_deficit, 0.25);
+        assert_eq!(factors[2].country_concentration, 0.0);
+        assert_eq!(factors[6].risk(), 1.0);
+
+        assert_eq!(summary.most_at_risk[0], 6);
+        assert!(summary.high_risk_nodes >= 1);
+        let average = factors.iter().map(|f| f.risk()).sum::<f64>() / 7.0;
+        assert!((summary.average_risk - average).abs() < 1e-12);
+    }
+
+    #[test]
+    fn hub_dependence_test() {
+        // Hub 0 with leaves 1 - 8, node 9 connected to leaves 1 and 2 and node 10 hanging on 9.
+        let mut adjacency = vec![(1..=8).collect::<Vec<usize>>()];
+        adjacency.extend((1..=8).map(|leaf| if leaf <= 2 { vec![0, 9] } else { vec![0] }));
+        adjacency.push(vec![1, 2, 10]);
+        adjacency.push(vec![9]);
+
+        let hubs = HubPeers::new(&adjacency);
+        let mut counts = vec![0; adjacency.len()];
+        let dependence = (0..adjacency.len())
+            .map(|idx| hubs.dependence(idx, &adjacency[idx], &mut counts))
+            .collect::<Vec<f64>>();
+
+        assert_eq!(hubs.is_hub, (0..11).map(|idx| idx == 0).collect::<Vec<_>>());
+        // Two of three peers of node 9 hang on the hub.
+        assert_eq!(dependence[9], 2.0 / 3.0);
+        // Peer of leaf 1 is the hub itself.
+        assert_eq!(dependence[1], 0.5);
+        assert_eq!(dependence[3], 1.0);
+        // The hub doesn't depend on itself.
+        assert_eq!(dependence[0], 0.0);
+        assert_eq!(dependence[10], 0.0);
+        assert!(counts.iter().all(|count| *count == 0));
+    }
+}
@@ -0,0 +1,138 @@
+use std::{error::Error, io};
+
+use thiserror::Error;
+
+/// Exit code used when reading or writing files fails.
+pub const EXIT_CODE_IO: u8 = 3;
+/// Exit code used when input data can't be parsed or output can't be serialized.
+pub const EXIT_CODE_PARSE: u8 = 4;
+/// Exit code used when configuration is invalid.
+pub const EXIT_CODE_CONFIG: u8 = 5;
+/// Exit code used when the network topology doesn't allow to continue safely.
+pub const EXIT_CODE_TOPOLOGY: u8 = 6;
+/// Exit code used when geolocation fails.
+pub const EXIT_CODE_GEOIP: u8 = 7;
+
+/// Result type used across crunchy.
+pub type Result<T> = std::result::Result<T, CrunchyError>;
+
+/// Errors reported by crunchy. Each category maps to a distinct process exit code, so scripts
+/// running crunchy can tell e.g. a corrupt sample from a dangerous network topology.
+#[derive(Debug, Error)]
+pub enum CrunchyError {
+    /// Reading or writing a file failed
+    #[error("{context}")]
+    Io {
+        context: String,
+        #[source]
+        source: io::Error,
+    },
+    /// Input data can't be parsed or output data can't be serialized
+    #[error("{context}")]
+    Parse {
+        context: String,
+        #[source]
+        source: Box<dyn Error + Send + Sync>,
+    },
+    /// Configuration is invalid
+    #[error("invalid configuration: {0}")]
+    Config(String),
+    /// Network topology doesn't allow to continue safely
+    #[error("dangerous network topology: {0}")]
+    Topology(String),
+    /// Geolocation failed
+    #[error("{context}")]
+    GeoIp {
+        context: String,
+        #[source]
+        source: Box<dyn Error + Send + Sync>,
+    },
+}
+
+impl CrunchyError {
+    /// Create IO error with the given context.
+    pub fn io(context: impl Into<String>, source: io::Error) -> Self {
+        CrunchyError::Io {
+            context: context.into(),
+            source,
+        }
+    }
+
+    /// Create parse error with the given context.
+    pub fn parse(
+        context: impl Into<String>,
+        source: impl Into<Box<dyn Error + Send + Sync>>,
+    ) -> Self {
+        CrunchyError::Parse {
+            context: context.into(),
+            source: source.into(),
+        }
+    }
+
+    /// Create GeoIP error with the given context.
+    pub fn geoip(
+        context: impl Into<String>,
+        source: impl Into<Box<dyn Error + Send + Sync>>,
+    ) -> Self {
+        CrunchyError::GeoIp {
+            context: context.into(),
+            source: source.into(),
+        }
+    }
+
+    /// Process exit code for the error category.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            CrunchyError::Io { .. } => EXIT_CODE_IO,
+            CrunchyError::Parse { .. } => EXIT_CODE_PARSE,
+            CrunchyError::Config(_) => EXIT_CODE_CONFIG,
+            CrunchyError::Topology(_) => EXIT_CODE_TOPOLOGY,
+            CrunchyError::GeoIp { .. } => EXIT_CODE_GEOIP,
+        }
+    }
+
+    /// Human-readable report containing the error and the whole chain of its causes.
+    pub fn report(&self) -> String {
+        let mut report = format!("error: {self}");
+        let mut source = self.source();
+        while let Some(cause) = source {
+            report.push_str(&format!("\n  caused by: {cause}"));
+            source = cause.source();
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_codes_are_distinct_test() {
+        let errors = [
+            CrunchyError::io("io", io::Error::from(io::ErrorKind::NotFound)),
+            CrunchyError::parse("parse", "bad json"),
+            CrunchyError::Config("config".to_owned()),
+            CrunchyError::Topology("topology".to_owned()),
+            CrunchyError::geoip("geoip", "bad cache"),
+        ];
+
+        let mut codes = errors.iter().map(|e| e.exit_code()).collect::<Vec<u8>>();
+        codes.sort();
+        codes.dedup();
+        assert_eq!(codes.len(), errors.len());
+    }
+
+    #[test]
+    fn report_contains_causes_test() {
+        let err = CrunchyError::io(
+            "could not open sample file sample.json",
+            io::Error::new(io::ErrorKind::NotFound, "No such file or directory"),
+        );
+
+        assert_eq!(
+            err.report(),
+            "error: could not open sample file sample.json\n  caused by: No such file or directory"
+        );
+    }
+}
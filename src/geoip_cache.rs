@@ -17,7 +17,10 @@ use ziggurat_core_geoip::{
     },
 };
 
-use crate::config::{GeoIPConfiguration, DEFAULT_KEEP_IN_CACHE_DAYS};
+use crate::{
+    config::{GeoIPConfiguration, DEFAULT_KEEP_IN_CACHE_DAYS},
+    error::{CrunchyError, Result},
+};
 
 #[derive(Clone, Serialize, Deserialize)]
 struct CachedIp {
@@ -61,20 +64,41 @@ impl GeoIPCache {
         self.providers.push(provider);
     }
 
-    /// Load the cache from the file.
-    pub async fn load(&self) -> Result<(), io::Error> {
-        let cache_string = fs::read_to_string(&self.cache_file)?;
+    /// Load the cache from the file. Returns `false` if there is no cache file yet (it's created
+    /// when the cache is saved).
+    pub async fn load(&self) -> Result<bool> {
+        let cache_string = match fs::read_to_string(&self.cache_file) {
+            Ok(cache_string) => cache_string,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => {
+                return Err(CrunchyError::io(
+                    format!("could not open cache file {}", self.cache_file.display()),
+                    e,
+                ))
+            }
+        };
 
         let mut cache = self.cache.write().await;
-        cache.entries = serde_json::from_str(&cache_string).unwrap();
-        Ok(())
+        cache.entries = serde_json::from_str(&cache_string).map_err(|e| {
+            CrunchyError::geoip(
+                format!("corrupted cache file {}", self.cache_file.display()),
+                e,
+            )
+        })?;
+        Ok(true)
     }
 
     /// Save the cache to the file.
-    pub async fn save(&self) -> Result<(), io::Error> {
+    pub async fn save(&self) -> Result<()> {
         let cache = self.cache.read().await;
-        let cache_string = serde_json::to_string(&cache.entries).unwrap();
-        fs::write(&self.cache_file, cache_string)
+        let cache_string = serde_json::to_string(&cache.entries)
+            .map_err(|e| CrunchyError::geoip("could not serialize cache", e))?;
+        fs::write(&self.cache_file, cache_string).map_err(|e| {
+            CrunchyError::io(
+                format!("could not write cache file {}", self.cache_file.display()),
+                e,
+            )
+        })
     }
 
     /// Function look in cache and if not found, it will call the providers to fetch new data and
@@ -100,6 +124,36 @@ impl GeoIPCache {
         None
     }
 
+    /// Number of entries in the cache (including expired ones).
+    pub async fn entries_count(&self) -> usize {
+        self.cache.read().await.entries.len()
+    }
+
+    /// Number of entries that are too old to be used.
+    pub async fn expired_count(&self) -> usize {
+        let cache = self.cache.read().await;
+        cache
+            .entries
+            .values()
+            .filter(|entry| self.is_expired(entry))
+            .count()
+    }
+
+    /// Remove all entries that are too old to be used. Returns number of removed entries.
+    pub async fn prune(&self) -> usize {
+        let mut rw_cache = self.cache.write().await;
+        let entries_count = rw_cache.entries.len();
+        rw_cache.entries.retain(|_, entry| !self.is_expired(entry));
+        entries_count - rw_cache.entries.len()
+    }
+
+    /// Check if the entry is too old to be used.
+    fn is_expired(&self, entry: &CachedIp) -> bool {
+        // Entries from the future (eg. clock changes) are treated as fresh ones.
+        entry.last_updated.elapsed().unwrap_or_default()
+            >= Duration::from_secs(60 * 60 * 24 * self.keep_in_cache_days as u64)
+    }
+
     async fn check_cache(&self, ip: IpAddr) -> Option<GeoInfo> {
         let mut remove_entry = false;
         {
@@ -107,9 +161,7 @@ impl GeoIPCache {
             let res = cache.entries.get(&ip);
             if let Some(entry) = res {
                 // Check if the entry is not too old.
-                if entry.last_updated.elapsed().unwrap()
-                    < Duration::from_secs(60 * 60 * 24 * self.keep_in_cache_days as u64)
-                {
+                if !self.is_expired(entry) {
                     return Some(entry.info.clone());
                 }
                 remove_entry = true;
@@ -125,36 +177,45 @@ impl GeoIPCache {
     }
 
     /// Configure the providers based on the configuration.
-    pub fn configure_providers(&mut self, config: &GeoIPConfiguration) {
+    pub fn configure_providers(&mut self, config: &GeoIPConfiguration) -> Result<()> {
         if config.ip2location_enable {
             let ipv6db = config
                 .ip2location_ipv6_db_path
                 .as_ref()
                 .map(|path| path.as_path().display().to_string());
 
+            let db_path = config.ip2location_db_path.as_ref().ok_or_else(|| {
+                CrunchyError::Config("IP2Location enabled but no database path set".to_owned())
+            })?;
+
             self.add_provider(Box::new(Ip2LocationService::new(
-                config
-                    .ip2location_db_path
-                    .as_ref()
-                    .unwrap()
-                    .to_str()
-                    .unwrap(),
+                &db_path.display().to_string(),
                 ipv6db,
             )));
         }
 
         if config.ipapico_enable {
+            let api_key = config.ipapico_api_key.as_ref().ok_or_else(|| {
+                CrunchyError::Config("ipapi.co enabled but no API key set".to_owned())
+            })?;
+
             self.add_provider(Box::new(IpGeolocateService::new(
                 BackendProvider::IpApiCo,
-                config.ipapico_api_key.as_ref().unwrap().as_str(),
+                api_key.as_str(),
             )));
         }
 
         if config.ipapicom_enable {
+            let api_key = config.ipapicom_api_key.as_ref().ok_or_else(|| {
+                CrunchyError::Config("ipapi.com enabled but no API key set".to_owned())
+            })?;
+
             self.add_provider(Box::new(IpGeolocateService::new(
                 BackendProvider::IpApiCom,
-                config.ipapicom_api_key.as_ref().unwrap().as_str(),
+                api_key.as_str(),
             )));
         }
+
+        Ok(())
     }
 }
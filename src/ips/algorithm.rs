@@ -16,29 +16,46 @@
 // Especially, there could be a need to add some modifiers to the ranking.
 
 use std::{
-    collections::{HashMap, HashSet, VecDeque},
+    collections::{HashMap, HashSet},
     fs,
     fs::File,
     io,
     io::Write,
     net::SocketAddr,
+    time::Instant,
 };
 
 use ziggurat_core_crawler::summary::NetworkType;
 
 use crate::{
-    config::GeoLocationMode,
+    centrality::{approximate_centralities, CentralityApproximation},
+    clustering::{compute_clustering, ClusteringSummary},
+    community::{detect_communities, CommunitySummary},
+    config::{AnalysisConfiguration, GeoLocationMode},
+    distance::{compute_distances, DistanceSummary},
+    eclipse::{compute_eclipse_risk, EclipseSummary},
+    error::{CrunchyError, Result},
     ips::{
-        config::IPSConfiguration,
+        config::{BridgeDetectionMode, IPSConfiguration},
+        degree::DegreeTarget,
+        diversity::PeerGroups,
+        explanation::{
+            save_explanations, NodeExplanation, PeerAction, PeerExplanation, PeerReason,
+            RatingBreakdown,
+        },
         graph_utils::{
-            construct_graph, filter_network, find_bridges, find_lowest_betweenness, remove_node,
+            adjacency_list, construct_graph, filter_network, find_bridges, find_exact_bridges,
+            find_lowest_betweenness, inter_community_connections,
         },
         normalization::NormalizationFactors,
-        peer::Peer,
-        statistics::{
-            degree_centrality_avg, generate_statistics, print_statistics, print_statistics_delta,
-        },
+        partition::{BridgeSuggestion, IslandReport, PartitionReport},
+        peer::{Peer, PeerDelta},
+        statistics::{generate_statistics, print_statistics, print_statistics_delta, Statistics},
     },
+    kcore::{core_numbers, CoreProfile},
+    nodes::{detect_components, graph_hash},
+    spectral::algebraic_connectivity,
+    timings::Timings,
     CrunchyState, Node,
 };
 
@@ -46,6 +63,10 @@ use crate::{
 #[derive(Default, Clone)]
 pub struct Ips {
     config: IPSConfiguration,
+    /// Configuration of the graph metrics computation
+    analysis: AnalysisConfiguration,
+    /// Durations of the phases of the last run
+    timings: Timings,
 }
 
 /// State structure containing all the information about the graph and nodes at some point
@@ -67,6 +88,21 @@ pub struct IpsState {
     pub closeness_factors: NormalizationFactors,
     /// Eigenvector factors used for normalization
     pub eigenvector_factors: NormalizationFactors,
+    /// Clustering coefficient factors used for normalization
+    pub clustering_factors: NormalizationFactors,
+    /// Transitivity and average clustering of the network
+    pub clustering: ClusteringSummary,
+    /// Core-periphery classification of the network
+    pub core_profile: CoreProfile,
+    /// Diameter, radius, average path length and hop distribution of the network
+    pub distances: DistanceSummary,
+    /// Second smallest eigenvalue of the Laplacian of the largest connected component (`None`
+    /// if the computation didn't converge)
+    pub algebraic_connectivity: Option<f64>,
+    /// Modularity and sizes of the communities found in the network
+    pub communities: CommunitySummary,
+    /// Eclipse risk figures of the network, computed only when nodes are prioritized by it
+    pub eclipse: Option<EclipseSummary>,
 }
 
 /// Internal structure for storing peer information
@@ -81,19 +117,62 @@ struct PeerEntry {
 }
 
 const NORMALIZE_TO_VALUE: f64 = 100.0;
-const NORMALIZE_HALF: f64 = NORMALIZE_TO_VALUE / 2.0;
-const NORMALIZE_2_3: f64 = NORMALIZE_TO_VALUE * 2.0 / 3.0;
-const NORMALIZE_1_3: f64 = NORMALIZE_TO_VALUE * 1.0 / 3.0;
 
-const ERR_GET_DEGREE: &str = "failed to get degree";
-const ERR_GET_EIGENVECTOR: &str = "failed to get eigenvector";
+/// Number of the best rated nodes of each massive island considered as bridge ends.
+const PARTITION_BRIDGE_CANDIDATES: usize = 5;
+/// Number of bridges suggested for each pair of massive islands.
+const PARTITION_BRIDGE_SUGGESTIONS: usize = 5;
+/// Number of nodes hop distances are measured from if `distance_sample_size` is not set. IPS
+/// recomputes the state several times per run, so all-sources searches would dominate it.
+const IPS_DISTANCE_SAMPLE_SIZE: usize = 1000;
+/// Relative drop of algebraic connectivity which is still taken as numerical noise - Lanczos
+/// iterations give slightly different results for the same graph with reordered connections.
+const CONNECTIVITY_TOLERANCE: f64 = 1e-6;
+
+/// Error of writing to the IPS log.
+fn log_error(e: io::Error) -> CrunchyError {
+    CrunchyError::io("could not write IPS log", e)
+}
+
+/// Normalization factors of the metric values, the network can't be empty.
+fn normalization_factors<T>(values: &[T]) -> Result<NormalizationFactors>
+where
+    T: PartialOrd + Into<f64> + Copy,
+{
+    NormalizationFactors::determine(values).ok_or_else(|| {
+        CrunchyError::Topology("can't normalize metrics of an empty network".to_owned())
+    })
+}
 
-const MASSIVE_ISLAND_PERCENTAGE: f64 = 0.1;
-const NODES_TO_BE_REMOVED_PERCENTAGE: f64 = 0.1;
+/// Check if the final algebraic connectivity is really lower than the initial one.
+fn lowers_connectivity(initial: f64, final_connectivity: f64) -> bool {
+    final_connectivity < initial * (1.0 - CONNECTIVITY_TOLERANCE)
+}
+
+/// Algebraic connectivity for the log.
+fn format_connectivity(connectivity: Option<f64>) -> String {
+    connectivity.map_or_else(|| "not converged".to_owned(), |value| value.to_string())
+}
+
+/// Error of the node metric missing in the computed metrics.
+fn missing_metric(metric: &str, addr: SocketAddr) -> CrunchyError {
+    CrunchyError::Topology(format!(
+        "{metric} of {addr} is missing in the computed metrics"
+    ))
+}
 
 impl Ips {
-    pub fn new(config: IPSConfiguration) -> Ips {
-        Ips { config }
+    pub fn new(config: IPSConfiguration, analysis: AnalysisConfiguration) -> Ips {
+        Ips {
+            config,
+            analysis,
+            timings: Timings::default(),
+        }
+    }
+
+    /// Durations of the phases of the last run.
+    pub fn timings(&self) -> &Timings {
+        &self.timings
     }
 
     /// Generate peer list - main function with The Algorithm
@@ -102,7 +181,7 @@ impl Ips {
         state: &CrunchyState,
         network: NetworkType,
         num_threads: usize,
-    ) -> Vec<Peer> {
+    ) -> Result<Vec<Peer>> {
         // Set up logging
         let output = match self.config.log_path {
             Some(ref path) => File::create(path).map(|f| Box::new(f) as Box<dyn Write>),
@@ -116,13 +195,52 @@ impl Ips {
 
         // Sanity check that each node is really connected to its peers and the peers also
         // have the node in their connections.
-        writeln!(o, "IPS algorithm started...").unwrap();
-        let start_time = std::time::Instant::now();
+        writeln!(o, "IPS algorithm started...").map_err(log_error)?;
+        let start_time = Instant::now();
+        self.timings = Timings::default();
+
+        let thresholds = &self.config.thresholds;
+        writeln!(
+            o,
+            "Thresholds: massive island {}, nodes to be removed {}, candidate multiplier {}, location ratings closer {:?}, distant {:?}",
+            thresholds.massive_island_percentage,
+            thresholds.nodes_to_be_removed_percentage,
+            thresholds.candidate_multiplier,
+            thresholds.closer_location_ratings,
+            thresholds.distant_location_ratings
+        )
+        .map_err(log_error)?;
+
+        let limit =
+            |limit: Option<usize>| limit.map_or("unlimited".to_owned(), |limit| limit.to_string());
+
+        let diversity = &self.config.diversity;
+        if diversity.is_enabled() {
+            writeln!(
+                o,
+                "Diversity constraint: max peers per subnet {}, per country {}",
+                limit(diversity.max_peers_per_subnet),
+                limit(diversity.max_peers_per_country)
+            )
+            .map_err(log_error)?;
+        }
+
+        let connection_limits = self.config.connection_limits();
+        if connection_limits.is_enabled() {
+            writeln!(
+                o,
+                "Connection limits for {network:?}: max outbound {}, inbound {}, total {}",
+                limit(connection_limits.max_outbound),
+                limit(connection_limits.max_inbound),
+                limit(connection_limits.max_total)
+            )
+            .map_err(log_error)?;
+        }
 
-        writeln!(o, "Checking for nodes connected to themselves...").unwrap();
+        writeln!(o, "Checking for nodes connected to themselves...").map_err(log_error)?;
         for (idx, node) in state.nodes.iter().enumerate() {
             if node.connections.contains(&idx) {
-                writeln!(o, "{} is connected to itself.", node.addr).unwrap();
+                writeln!(o, "{} is connected to itself.", node.addr).map_err(log_error)?;
             }
 
             for peer in &node.connections {
@@ -132,12 +250,17 @@ impl Ips {
                         "{} is not connected to {} but {} have a connection to it",
                         node.addr, state.nodes[*peer].addr, node.addr
                     )
-                    .unwrap();
+                    .map_err(log_error)?;
                 }
             }
         }
 
         let network_nodes = filter_network(&state.nodes, network);
+        if network_nodes.is_empty() {
+            return Err(CrunchyError::Topology(format!(
+                "there are no {network:?} nodes in the state"
+            )));
+        }
 
         writeln!(
             o,
@@ -147,93 +270,233 @@ impl Ips {
                 .iter()
                 .fold(0, |acc, n| acc + n.connections.len())
         )
-        .unwrap();
+        .map_err(log_error)?;
+
+        writeln!(o, "Generating initial network state and its statistics... ")
+            .map_err(log_error)?;
+
+        if let Some(approximation) =
+            CentralityApproximation::from_config(&self.analysis, network_nodes.len())
+        {
+            write!(
+                o,
+                "Betweenness and closeness are approximated using {} of {} nodes as pivots (seed {}",
+                approximation.pivots, approximation.nodes, approximation.seed
+            )
+            .map_err(log_error)?;
+            if let Some(error_bound) = approximation.error_bound {
+                write!(o, ", error bound {error_bound}").map_err(log_error)?;
+            }
+            writeln!(o, ")").map_err(log_error)?;
+        }
 
-        writeln!(o, "Generating initial network state and its statistics... ").unwrap();
+        // Centralities stored in the state are still valid if they were computed for exactly
+        // the same graph - in such case there is no need to run the most expensive part again.
+        let reuse_centralities =
+            !state.graph_hash.is_empty() && graph_hash(&network_nodes) == state.graph_hash;
+        if reuse_centralities {
+            writeln!(
+                o,
+                "Graph is unchanged (hash {}), reusing stored centralities",
+                state.graph_hash
+            )
+            .map_err(log_error)?;
+        }
 
         // This is the working set of factors.
-        let mut working_state = self.generate_state(&network_nodes, true, num_threads);
+        let mut working_state =
+            self.generate_state(&network_nodes, !reuse_centralities, num_threads)?;
         let mut final_state = working_state.clone();
 
         let initial_statistics = generate_statistics(&working_state);
+        let initial_connectivity = working_state.algebraic_connectivity;
 
-        writeln!(o, "Statistics for the initial network:").unwrap();
-        print_statistics(&mut o, &initial_statistics);
+        writeln!(o, "Statistics for the initial network:").map_err(log_error)?;
+        print_statistics(&mut o, &initial_statistics).map_err(log_error)?;
 
         writeln!(
             o,
             "Generated initial state and statistics in {} s",
             start_time.elapsed().as_secs()
         )
-        .unwrap();
+        .map_err(log_error)?;
 
         if let Some(path) = &self.config.vanilla_peer_file_path {
-            let peerlist = serde_json::to_string(&working_state.peer_list).unwrap();
-            fs::write(path, peerlist).unwrap();
+            let peerlist = serde_json::to_string(&working_state.peer_list)
+                .map_err(|e| CrunchyError::parse("could not serialize vanilla peer list", e))?;
+            fs::write(path, peerlist).map_err(|e| {
+                CrunchyError::io(
+                    format!("could not write vanilla peer file {}", path.display()),
+                    e,
+                )
+            })?;
         }
 
+        self.timings
+            .record("ips_initial_state", start_time.elapsed());
+
         // Phase 1: Security checks
+        let phase_start = Instant::now();
 
         // Detect islands
         let islands = self.detect_islands(&working_state.nodes);
+        // Island of every node - set only if the network is partitioned, so peers are then
+        // looked for within the node's island.
+        let mut island_of = None;
         if islands.len() > 1 {
             // Check if we're talking about massive islands or just a few nodes
-            let mut massive_islands_count = 0;
-            for island in &islands {
-                // Check if any island is more than some % of the network
-                if island.len()
-                    > (working_state.nodes.len() as f64 * MASSIVE_ISLAND_PERCENTAGE).round()
-                        as usize
-                {
-                    massive_islands_count += 1;
-                }
-            }
+            let massive_island_size = (working_state.nodes.len() as f64
+                * self.config.thresholds.massive_island_percentage)
+                .round() as usize;
+            let massive_islands_count =
+                self.massive_islands_count(&islands, working_state.nodes.len());
 
             if massive_islands_count > 1 {
-                // We need to break here. Merging big islands can be a very complex task especially
-                // when they started to live their lives and created their own blockchain history
-                // after separation.
-                panic!("There are more than one massive island in the network. It is not possible to merge them automatically.");
-            }
+                // Merging big islands can be a very complex task especially when they started to
+                // live their lives and created their own blockchain history after separation.
+                // That's left to the operators - they get the report and peer lists are
+                // generated within each island.
+                writeln!(
+                    o,
+                    "IPS detected {massive_islands_count} massive islands, they won't be merged - peer lists are generated within each island"
+                )
+                .map_err(log_error)?;
+
+                let report =
+                    self.generate_partition_report(&working_state, &islands, massive_island_size);
+                self.print_partition_report(&mut o, &report)
+                    .map_err(log_error)?;
+                if let Some(path) = &self.config.partition_report_path {
+                    report.save(path)?;
+                    writeln!(o, "Partition report written to {}", path.display())
+                        .map_err(log_error)?;
+                }
 
-            writeln!(
-                o,
-                "IPS detected no massive islands. However, there are some disconnected nodes."
-            )
-            .unwrap();
+                let mut islands_of_nodes = vec![0; working_state.nodes.len()];
+                for (island_id, island) in islands.iter().enumerate() {
+                    for node_idx in island {
+                        islands_of_nodes[*node_idx] = island_id;
+                    }
+                }
+                island_of = Some(islands_of_nodes);
+            } else {
+                writeln!(
+                    o,
+                    "IPS detected no massive islands. However, there are some disconnected nodes."
+                )
+                .map_err(log_error)?;
+            }
         } else {
             // There are no islands
-            writeln!(o, "IPS detected no islands").unwrap();
+            writeln!(o, "IPS detected no islands").map_err(log_error)?;
         }
 
         if !self.check_and_fix_integrity_upon_removal(&mut working_state) {
-            writeln!(o, "There were hot nodes that can be dangerous for the network! Recalculating graph...").unwrap();
-            working_state = self.generate_state(&working_state.nodes, true, num_threads);
+            writeln!(o, "There were hot nodes that can be dangerous for the network! Recalculating graph...").map_err(log_error)?;
+            working_state = self.generate_state(&working_state.nodes, true, num_threads)?;
         } else {
             // There are no hot nodes
-            writeln!(o, "IPS detected no fragmentation possibility even when top nodes would be disconnected").unwrap();
+            writeln!(o, "IPS detected no fragmentation possibility even when top nodes would be disconnected").map_err(log_error)?;
         }
 
         // Now take the current params
-        let degree_avg = degree_centrality_avg(&working_state.degrees);
+        let degree_target = DegreeTarget::new(self.config.desired_degree, &working_state.degrees);
+        writeln!(o, "Desired degree policy: {degree_target}").map_err(log_error)?;
 
         // Detect possible bridges
-        let bridges = find_bridges(
-            &working_state.nodes,
-            self.config.bridge_threshold_adjustment,
-        );
+        let exact_bridges = find_exact_bridges(&working_state.nodes);
+        writeln!(
+            o,
+            "IPS detected {} bridges and {} articulation points",
+            exact_bridges.bridges.len(),
+            exact_bridges.articulation_points.len()
+        )
+        .map_err(log_error)?;
+
+        let core_nodes_count = working_state
+            .nodes
+            .iter()
+            .filter(|node| working_state.core_profile.is_core(node.core_number))
+            .count();
+        writeln!(
+            o,
+            "Network core (core number at least {}) contains {} nodes, {} nodes are on the periphery",
+            working_state.core_profile.threshold,
+            core_nodes_count,
+            working_state.nodes.len() - core_nodes_count
+        )
+        .map_err(log_error)?;
+
+        let inter_community_count = inter_community_connections(&working_state.nodes);
+        writeln!(
+            o,
+            "Network contains {} communities (modularity {:.3}) with {} connections between them",
+            working_state.communities.sizes.len(),
+            working_state.communities.modularity,
+            inter_community_count
+        )
+        .map_err(log_error)?;
+
+        let bridges = match self.config.bridge_detection {
+            BridgeDetectionMode::Heuristic => find_bridges(
+                &working_state.nodes,
+                self.config.bridge_threshold_adjustment,
+            ),
+            BridgeDetectionMode::Exact => exact_bridges.bridge_map(),
+            BridgeDetectionMode::Union => {
+                let mut bridges = find_bridges(
+                    &working_state.nodes,
+                    self.config.bridge_threshold_adjustment,
+                );
+                for (node_idx, peers) in exact_bridges.bridge_map() {
+                    bridges.entry(node_idx).or_default().extend(peers);
+                }
+                bridges
+            }
+        };
+
+        self.timings
+            .record("ips_security_checks", phase_start.elapsed());
 
         // Phase 2: Generate peer list using MCDA optimization.
+        let phase_start = Instant::now();
 
-        writeln!(o, "The MCDA procedure is starting...").unwrap();
+        writeln!(o, "The MCDA procedure is starting...").map_err(log_error)?;
 
         // Node rating can be split into two parts: constant and variable depending on the node's
         // location. Now we can compute each node's constant rating based on some graph params.
         let const_factors = self.calculate_const_factors(&working_state);
 
+        // Decisions made for every node, used to explain the peer lists.
+        let mut desired_degrees = vec![0; working_state.nodes.len()];
+        let mut reasons = vec![HashMap::new(); working_state.nodes.len()];
+        // Number of candidates skipped due to diversity limits.
+        let mut diversity_skipped = 0;
+        // Number of candidates skipped as they can't accept another connection.
+        let mut full_skipped = 0;
+
+        // Nodes processed first have the widest choice of new peers - candidates which already
+        // changed too much are skipped later on. So the most endangered nodes can go first.
+        let mut order = (0..working_state.nodes.len()).collect::<Vec<usize>>();
+        if self.config.prioritize_eclipse_risk {
+            order.sort_by(|a, b| {
+                let risk = |idx: usize| working_state.nodes[idx].eclipse_risk.unwrap_or_default();
+                risk(*b).total_cmp(&risk(*a))
+            });
+            writeln!(
+                o,
+                "Nodes are processed by eclipse risk, {} high risk nodes go first",
+                working_state
+                    .eclipse
+                    .as_ref()
+                    .map_or(0, |eclipse| eclipse.high_risk_nodes)
+            )
+            .map_err(log_error)?;
+        }
+
         // Iterate over nodes to generate peerlist entry for each node
-        for (node_idx, node) in working_state.nodes.iter().enumerate() {
-            let node_addr = node.addr;
+        for node_idx in order {
+            let node = &working_state.nodes[node_idx];
 
             // Clone const factors for each node to be able to modify them
             let mut peer_ratings = const_factors.clone();
@@ -254,14 +517,19 @@ impl Ips {
             }
 
             // Get current node's degree for further computations
-            let degree = *working_state.degrees.get(&node_addr).expect(ERR_GET_DEGREE);
+            let degree = node.degree as u32;
 
             // 2 - Calculate desired vertex degree
-            // In the first iteration we will use degree average so all nodes should pursue to
-            // that level. That could be bad if graph's vertexes have very high (or low) degrees
-            // and therefore, delta is very high (or low) too. But until we have some better idea
-            // this one is the best we can do to keep up with the graph.
-            let desired_degree = degree_avg.round() as u32;
+            // The policy is configurable. By default all nodes pursue the degree average. That
+            // could be bad if graph's vertexes have very high (or low) degrees and therefore,
+            // delta is very high (or low) too - median, percentile or moving only part of the
+            // way toward the mean are more robust then.
+            // Nodes can't pursue more peers than their connection slots allow.
+            let mut desired_degree = degree_target.desired_degree(degree);
+            if let Some(max_total) = connection_limits.max_total {
+                desired_degree = desired_degree.min(max_total as u32);
+            }
+            desired_degrees[node_idx] = desired_degree;
 
             // 3 - Calculate how many peers to add or delete from peerlist
             let mut peers_to_delete_count = if desired_degree < degree {
@@ -293,6 +561,14 @@ impl Ips {
                 peers_to_add_count = self.config.change_no_more;
             }
 
+            // Never recommend more peers than the node can open - peers which are going to be
+            // deleted free their slots.
+            let peers_kept = curr_peer_ratings
+                .len()
+                .saturating_sub(peers_to_delete_count as usize);
+            peers_to_add_count =
+                peers_to_add_count.min(connection_limits.outbound_room(peers_kept) as u32);
+
             // Remove potential peers identified to have too high degree and have already
             // been processed by the algorithm
             peer_ratings.retain(|x| {
@@ -312,26 +588,92 @@ impl Ips {
             // Remove node itself to ensure we don't add it to peerlist
             peer_ratings.retain(|x| x.index != node_idx);
 
+            // Never connect islands of the partitioned network
+            if let Some(island_of) = &island_of {
+                peer_ratings.retain(|x| island_of[x.index] == island_of[node_idx]);
+            }
+
             // Sort peers by rating (highest first)
-            curr_peer_ratings.sort_by(|a, b| b.rating.partial_cmp(&a.rating).unwrap());
+            curr_peer_ratings.sort_by(|a, b| b.rating.total_cmp(&a.rating));
 
             // 4 - Choose peers to delete from peerlist (based on ranking)
+            // Protected peers are put back once the deletion is done.
+            let mut protected_peers = Vec::new();
+            let mut peers_changed = false;
             while peers_to_delete_count > 0 {
                 if let Some(peer) = curr_peer_ratings.pop() {
                     // Check if we're not deleting a bridge
                     if bridges.contains_key(&peer.index) && bridges[&peer.index].contains(&node_idx)
                     {
+                        reasons[node_idx].insert(peer.index, PeerReason::BridgeProtected);
+                        protected_peers.push(peer);
                         continue;
                     }
+
+                    // Check if we're not deleting the last link to another community
+                    let community = node.community;
+                    if self.config.inter_community_links
+                        && working_state.nodes[peer.index].community != community
+                        && !curr_peer_ratings
+                            .iter()
+                            .chain(&protected_peers)
+                            .any(|x| working_state.nodes[x.index].community != community)
+                    {
+                        reasons[node_idx].insert(peer.index, PeerReason::InterCommunityLink);
+                        protected_peers.push(peer);
+                        continue;
+                    }
+                    let reason = if desired_degree < degree {
+                        PeerReason::OverDegree
+                    } else {
+                        PeerReason::LowRating
+                    };
+                    reasons[node_idx].insert(peer.index, reason);
                     curr_peer_ratings.retain(|x| x != &peer);
+                    peers_changed = true;
                 }
                 peers_to_delete_count -= 1;
             }
+            curr_peer_ratings.extend(protected_peers);
 
             // 5 - Find peers to add from selected peers (based on rating)
             if peers_to_add_count > 0 {
                 // Sort peers by rating
-                peer_ratings.sort_by(|a, b| b.rating.partial_cmp(&a.rating).unwrap());
+                peer_ratings.sort_by(|a, b| b.rating.total_cmp(&a.rating));
+
+                // Periphery nodes can be requested to look for new peers in the network core
+                // first - stable sort keeps the rating order within both groups.
+                if self.config.prefer_core_connections
+                    && !working_state.core_profile.is_core(node.core_number)
+                {
+                    peer_ratings.sort_by_key(|x| {
+                        !working_state
+                            .core_profile
+                            .is_core(working_state.nodes[x.index].core_number)
+                    });
+                }
+
+                // Nodes linked only to their own community look for new peers in other
+                // communities first, so the network doesn't split along community lines.
+                if self.config.inter_community_links
+                    && !final_state.nodes[node_idx]
+                        .connections
+                        .iter()
+                        .any(|peer| working_state.nodes[*peer].community != node.community)
+                {
+                    peer_ratings
+                        .sort_by_key(|x| working_state.nodes[x.index].community == node.community);
+                }
+
+                // Peers the node keeps, counted per subnet and country.
+                let mut peer_groups = PeerGroups::new(
+                    &self.config.diversity,
+                    curr_peer_ratings
+                        .iter()
+                        .map(|x| &working_state.nodes[x.index]),
+                );
+                // The best rating of the candidates skipped due to diversity limits.
+                let mut best_skipped_rating = None;
 
                 let mut candidates = peer_ratings
                     .iter()
@@ -346,29 +688,92 @@ impl Ips {
                             return false;
                         }
 
+                        // Check if the peer can accept another connection
+                        if !connection_limits
+                            .accepts_inbound(final_state.nodes[x.index].connections.len())
+                        {
+                            full_skipped += 1;
+                            return false;
+                        }
+
+                        // Check if we're not exceeding diversity limits with current peers -
+                        // both ours and the peer's, as the connection is added to both lists
+                        let peer_allows = || {
+                            PeerGroups::new(
+                                &self.config.diversity,
+                                final_state.nodes[x.index]
+                                    .connections
+                                    .iter()
+                                    .map(|peer| &working_state.nodes[*peer]),
+                            )
+                            .allows(node)
+                        };
+                        if !peer_groups.allows(&working_state.nodes[x.index])
+                            || (self.config.diversity.is_enabled() && !peer_allows())
+                        {
+                            diversity_skipped += 1;
+                            best_skipped_rating = best_skipped_rating
+                                .map_or(Some(x.rating), |best: f64| Some(best.max(x.rating)));
+                            return false;
+                        }
+
                         true
                     })
-                    .take((peers_to_add_count * 2) as usize) // Take twice as many candidates
+                    // Take more candidates than needed
+                    .take(
+                        (peers_to_add_count * self.config.thresholds.candidate_multiplier) as usize,
+                    )
                     .copied()
                     .collect::<Vec<_>>();
 
-                // Here we have 2*peers_to_add_count candidates to add sorted by ranking.
-                // We need to choose best ones from them - let's choose those with lowest
+                // Here we have candidate_multiplier * peers_to_add_count candidates to add sorted
+                // by ranking. We need to choose best ones from them - let's choose those with lowest
                 // betweenness factor - just to avoid creating "hot" nodes that have very high
                 // importance to the network which can be risky if such node goes down.
                 candidates.sort_by(|a, b| {
                     working_state.nodes[a.index]
                         .betweenness
-                        .partial_cmp(&working_state.nodes[b.index].betweenness)
-                        .unwrap()
+                        .total_cmp(&working_state.nodes[b.index].betweenness)
                 });
 
-                for peer in candidates.iter().take(peers_to_add_count as usize) {
+                let reason = if desired_degree > degree {
+                    PeerReason::UnderDegree
+                } else {
+                    PeerReason::HighRating
+                };
+                let mut skipped_candidate = false;
+                let mut added_count = 0;
+                for peer in &candidates {
+                    if added_count == peers_to_add_count {
+                        break;
+                    }
+
+                    // Candidates picked earlier may have used up the diversity limits.
+                    let peer_node = &working_state.nodes[peer.index];
+                    if !peer_groups.allows(peer_node) {
+                        diversity_skipped += 1;
+                        skipped_candidate = true;
+                        continue;
+                    }
+                    peer_groups.add(peer_node);
+                    added_count += 1;
+
+                    let forced_by_diversity = skipped_candidate
+                        || best_skipped_rating.is_some_and(|best| best > peer.rating);
+                    let reason = if forced_by_diversity {
+                        PeerReason::DiversityPick
+                    } else {
+                        reason
+                    };
+                    reasons[node_idx].insert(peer.index, reason);
                     curr_peer_ratings.push(*peer);
                     final_state.nodes[peer.index].connections.push(node_idx);
+                    peers_changed = true;
                 }
+            }
 
-                // Write new node set
+            // Write new node set - nodes without free slots still shed their peers
+            if peers_changed {
                 final_state.nodes[node_idx].connections = curr_peer_ratings
                     .iter()
                     .map(|x| x.index)
@@ -385,91 +790,216 @@ impl Ips {
             }
         }
 
+        if self.config.diversity.is_enabled() {
+            writeln!(
+                o,
+                "Diversity constraint skipped {diversity_skipped} candidates"
+            )
+            .map_err(log_error)?;
+        }
+
+        if connection_limits.is_enabled() {
+            writeln!(
+                o,
+                "Connection limits skipped {full_skipped} candidates without free slots"
+            )
+            .map_err(log_error)?;
+        }
+
+        // Communities are still the ones found in the initial network.
+        writeln!(
+            o,
+            "Connections between initial communities: {} before, {} after",
+            inter_community_count,
+            inter_community_connections(&final_state.nodes)
+        )
+        .map_err(log_error)?;
+
         writeln!(
             o,
             "All IPS computations done in {} s from IPS start",
             start_time.elapsed().as_secs()
         )
-        .unwrap();
+        .map_err(log_error)?;
+
+        self.timings.record("ips_mcda", phase_start.elapsed());
 
-        final_state = self.generate_state(&final_state.nodes, true, num_threads);
+        let phase_start = Instant::now();
+        final_state = self.generate_state(&final_state.nodes, true, num_threads)?;
 
         let final_statistics = generate_statistics(&final_state);
-        writeln!(o, "Statistics for the final network:").unwrap();
-        print_statistics(&mut o, &final_statistics);
+        self.timings
+            .record("ips_final_recomputation", phase_start.elapsed());
+        writeln!(o, "Statistics for the final network:").map_err(log_error)?;
+        print_statistics(&mut o, &final_statistics).map_err(log_error)?;
 
         writeln!(
             o,
             "Comparing if network parameters got changed on plus or minus:"
         )
-        .unwrap();
-        print_statistics_delta(&mut o, &final_statistics, &initial_statistics);
+        .map_err(log_error)?;
+        print_statistics_delta(&mut o, &final_statistics, &initial_statistics)
+            .map_err(log_error)?;
+
+        if self.config.reject_lower_connectivity {
+            match (initial_connectivity, final_state.algebraic_connectivity) {
+                (Some(initial), Some(last)) if lowers_connectivity(initial, last) => {
+                    writeln!(
+                        o,
+                        "IPS result rejected: algebraic connectivity would drop from {initial} to {last}"
+                    )
+                    .map_err(log_error)?;
+                    return Err(CrunchyError::Topology(format!(
+                        "IPS result lowers algebraic connectivity from {initial} to {last}"
+                    )));
+                }
+                (Some(_), Some(_)) => {}
+                (initial, last) => {
+                    // Unconverged estimate could reject or accept the result arbitrarily.
+                    writeln!(
+                        o,
+                        "Algebraic connectivity check skipped: initial {}, final {}",
+                        format_connectivity(initial),
+                        format_connectivity(last)
+                    )
+                    .map_err(log_error)?;
+                }
+            }
+        }
+
+        if let Some(path) = &self.config.explanation_file_path {
+            let explanations = self.explain_peer_lists(
+                &network_nodes,
+                &final_state.nodes,
+                &working_state,
+                &desired_degrees,
+                &reasons,
+            );
+            save_explanations(&explanations, path)?;
+            writeln!(o, "Peer list explanations written to {}", path.display())
+                .map_err(log_error)?;
+        }
+
+        let delta = PeerDelta::new(
+            &Peer::generate_all_peerlists(&network_nodes),
+            &final_state.peer_list,
+        );
+        writeln!(
+            o,
+            "Peer list changes: {} nodes changed ({:.2}%), {} peers added, {} peers removed, churn {:.2}%",
+            delta.churn.nodes_changed,
+            delta.churn.nodes_changed_share * 100.0,
+            delta.churn.added,
+            delta.churn.removed,
+            delta.churn.churn_rate * 100.0
+        )
+        .map_err(log_error)?;
+        if let Some(path) = &self.config.delta_file_path {
+            delta.save(path)?;
+            writeln!(o, "Peer list delta written to {}", path.display()).map_err(log_error)?;
+        }
+
+        self.timings.print(&mut o).map_err(log_error)?;
 
         writeln!(
             o,
             "IPS has been working for {} seconds",
             start_time.elapsed().as_secs()
         )
-        .unwrap();
+        .map_err(log_error)?;
+
+        Ok(final_state.peer_list)
+    }
+
+    /// Recompute all metrics of the given nodes (centralities included) and their statistics.
+    /// Nodes are returned with the recomputed metrics.
+    pub fn recompute_network(
+        &self,
+        nodes: &[Node],
+        num_threads: usize,
+    ) -> Result<(Vec<Node>, Statistics)> {
+        let state = self.generate_state(nodes, true, num_threads)?;
+        let statistics = generate_statistics(&state);
+        Ok((state.nodes, statistics))
+    }
 
-        final_state.peer_list
+    /// Print statistics of the given nodes. Centralities stored in the nodes are used as they
+    /// are and hop distances are sampled, so nothing heavy is recomputed.
+    pub fn print_network_statistics(&self, nodes: &[Node], o: &mut Box<dyn Write>) -> Result<()> {
+        // Number of threads doesn't matter as centralities are not recomputed.
+        let state = self.generate_state(nodes, false, 1)?;
+        print_statistics(o, &generate_statistics(&state)).map_err(log_error)
     }
 
     // Helper functions
 
+    /// Number of islands with more than the massive island percentage of the given number of
+    /// nodes.
+    fn massive_islands_count(&self, islands: &[HashSet<usize>], nodes_count: usize) -> usize {
+        let massive_island_size = (nodes_count as f64
+            * self.config.thresholds.massive_island_percentage)
+            .round() as usize;
+        islands
+            .iter()
+            .filter(|island| island.len() > massive_island_size)
+            .count()
+    }
+
     /// Check integrity of the network after removing some percent of the nodes with highest
-    /// betweenness factor.
+    /// betweenness factor. Integrity is lost if the removal creates new massive islands, so an
+    /// already partitioned network is checked only for further fragmentation.
     /// Return true if integrity is preserved, false otherwise. If false is returned the caller
     /// should try to regenerate the network.
     fn check_and_fix_integrity_upon_removal(&self, state: &mut IpsState) -> bool {
-        let mut high_betweenness = state
-            .nodes
-            .iter()
-            .map(|x| x.betweenness)
-            .collect::<Vec<f64>>();
-
-        high_betweenness.sort_by(|a, b| b.partial_cmp(a).unwrap());
-
-        let mut test_state = state.clone();
-        let mut removed_idx = Vec::new();
-
         // Take some % of nodes with highest betweenness
-        let nodes_to_remove =
-            (high_betweenness.len() as f64 * NODES_TO_BE_REMOVED_PERCENTAGE).round() as usize;
-        for b in high_betweenness.iter().take(nodes_to_remove) {
-            let idx = test_state
-                .nodes
-                .iter()
-                .position(|x| x.betweenness == *b)
-                .unwrap();
-            remove_node(&mut test_state.nodes, idx);
-            removed_idx.push(idx);
-        }
-
-        let islands = self.detect_islands(&test_state.nodes);
-        let mut massive_island = 0;
-        if islands.len() > 1 {
-            // Consider network as not integral if there are more than 1 islands with at least
-            // some % of nodes. Don't consider islands with less than some % of nodes as they would
-            // probably have no meaning for the network itself.
-            for island in islands.iter() {
-                if island.len()
-                    > (test_state.nodes.len() as f64 * MASSIVE_ISLAND_PERCENTAGE).round() as usize
-                {
-                    massive_island += 1;
-                }
+        let nodes_to_remove = (state.nodes.len() as f64
+            * self.config.thresholds.nodes_to_be_removed_percentage)
+            .round() as usize;
+        let mut removed_idx = (0..state.nodes.len()).collect::<Vec<usize>>();
+        removed_idx.sort_by(|a, b| {
+            state.nodes[*b]
+                .betweenness
+                .total_cmp(&state.nodes[*a].betweenness)
+        });
+        removed_idx.truncate(nodes_to_remove);
+
+        // Removed nodes are just disconnected, so indices stay the same as in the state.
+        let removed = removed_idx.iter().copied().collect::<HashSet<usize>>();
+        let mut test_nodes = state.nodes.clone();
+        for (idx, node) in test_nodes.iter_mut().enumerate() {
+            if removed.contains(&idx) {
+                node.connections.clear();
+            } else {
+                node.connections.retain(|peer| !removed.contains(peer));
             }
         }
 
-        if massive_island > 1 {
-            // If we're able to fragment the network into more than 1 massive islands then try to fix it
-            // by adding new connections between highest betweenness node's neighbors.
+        // Consider network as not integral if there are more islands with at least some % of
+        // nodes than before. Don't consider islands with less than some % of nodes as they would
+        // probably have no meaning for the network itself.
+        let massive_islands_before =
+            self.massive_islands_count(&self.detect_islands(&state.nodes), state.nodes.len());
+        let massive_islands_after = self.massive_islands_count(
+            &self.detect_islands(&test_nodes),
+            state.nodes.len() - nodes_to_remove,
+        );
+
+        if massive_islands_after > massive_islands_before.max(1) {
+            // If we're able to fragment the network into more massive islands then try to fix it
+            // by adding new connections between highest betweenness node's neighbors. Neighbors
+            // are always in the node's island, so islands of a partitioned network are not joined.
             for node_idx in removed_idx {
                 let mut conns = state.nodes[node_idx].connections.clone();
-                let node_a_idx = find_lowest_betweenness(&conns, state);
+                conns.retain(|x| *x != node_idx);
+                let Some(node_a_idx) = find_lowest_betweenness(&conns, state) else {
+                    continue;
+                };
                 // Remove node_a_idx from conns
                 conns.retain(|x| *x != node_a_idx);
-                let node_b_idx = find_lowest_betweenness(&conns, state);
+                // Node with a single neighbor has nothing to connect it with.
+                let Some(node_b_idx) = find_lowest_betweenness(&conns, state) else {
+                    continue;
+                };
 
                 state.nodes[node_a_idx].connections.push(node_b_idx);
                 state.nodes[node_b_idx].connections.push(node_a_idx);
@@ -483,54 +1013,140 @@ impl Ips {
     /// Generate state for IPS
     /// If generate_full is true, then it will generate full state for IPS. If false then
     /// it will not re-run betweenness and closeness centrality calculations.
-    fn generate_state(&self, nodes: &[Node], generate_full: bool, num_threads: usize) -> IpsState {
+    fn generate_state(
+        &self,
+        nodes: &[Node],
+        generate_full: bool,
+        num_threads: usize,
+    ) -> Result<IpsState> {
+        if nodes.is_empty() {
+            return Err(CrunchyError::Topology(
+                "there are no nodes to generate the state for".to_owned(),
+            ));
+        }
+
         let mut ips_state = IpsState {
             nodes: nodes.to_vec(),
             ..Default::default()
         };
 
         let mut graph = construct_graph(nodes);
+        let adjacency = adjacency_list(nodes);
 
         if generate_full {
-            let closeness = graph.closeness_centrality(num_threads);
-            let betweenness = graph.betweenness_centrality(num_threads, false);
-
-            // Recalculate factors with new graph
-            for node in ips_state.nodes.iter_mut() {
-                let addr = node.addr;
-                node.betweenness = *betweenness.get(&addr).expect("can't fetch betweenness");
-                node.closeness = *closeness.get(&addr).expect("can't fetch closeness");
+            if let Some(approximation) =
+                CentralityApproximation::from_config(&self.analysis, nodes.len())
+            {
+                let (betweenness, closeness) =
+                    approximate_centralities(&adjacency, &approximation, num_threads);
+                for (idx, node) in ips_state.nodes.iter_mut().enumerate() {
+                    node.betweenness = betweenness[idx];
+                    node.closeness = closeness[idx];
+                }
+            } else {
+                let closeness = graph.closeness_centrality(num_threads);
+                let betweenness = graph.betweenness_centrality(num_threads, false);
+
+                // Recalculate factors with new graph
+                for node in ips_state.nodes.iter_mut() {
+                    let addr = node.addr;
+                    node.betweenness = *betweenness
+                        .get(&addr)
+                        .ok_or_else(|| missing_metric("betweenness", addr))?;
+                    node.closeness = *closeness
+                        .get(&addr)
+                        .ok_or_else(|| missing_metric("closeness", addr))?;
+                }
             }
         }
 
         ips_state.degrees = graph.degree_centrality();
         ips_state.eigenvalues = graph.eigenvalue_centrality();
 
-        ips_state.degree_factors = NormalizationFactors::determine(
-            &ips_state.degrees.values().cloned().collect::<Vec<u32>>(),
-        )
-        .expect("can't calculate degree factors");
+        let (coefficients, clustering) = compute_clustering(&adjacency);
+        ips_state.clustering = clustering;
+        ips_state.clustering_factors = normalization_factors(&coefficients)?;
+
+        let core_numbers = core_numbers(&adjacency);
+        ips_state.core_profile = CoreProfile::new(&core_numbers, self.analysis.core_threshold);
+
+        let (eccentricities, distances) = compute_distances(
+            &adjacency,
+            Some(
+                self.analysis
+                    .distance_sample_size
+                    .unwrap_or(IPS_DISTANCE_SAMPLE_SIZE),
+            ),
+            self.analysis.seed,
+            num_threads,
+        );
+        ips_state.distances = distances;
+        ips_state.algebraic_connectivity = algebraic_connectivity(&adjacency, self.analysis.seed);
+
+        let (communities, community_summary) = detect_communities(
+            &adjacency,
+            self.analysis.community_algorithm,
+            self.analysis.seed,
+        );
+        ips_state.communities = community_summary;
+
+        // Risk is left unset unless it's needed - the value of the original network would be
+        // stale for the network changed by IPS.
+        let eclipse_factors = if self.config.prioritize_eclipse_risk {
+            let (eclipse_factors, eclipse) =
+                compute_eclipse_risk(nodes, &adjacency, &core_numbers, num_threads);
+            ips_state.eclipse = Some(eclipse);
+            eclipse_factors.iter().map(|f| Some(f.risk())).collect()
+        } else {
+            vec![None; nodes.len()]
+        };
+
+        for (((((node, coefficient), core_number), eccentricity), community), eclipse_risk) in
+            ips_state
+                .nodes
+                .iter_mut()
+                .zip(coefficients)
+                .zip(core_numbers)
+                .zip(eccentricities)
+                .zip(communities)
+                .zip(eclipse_factors)
+        {
+            node.community = community;
+            node.eclipse_risk = eclipse_risk;
+            node.clustering = coefficient;
+            node.core_number = core_number;
+            node.eccentricity = eccentricity;
+            node.degree = *ips_state
+                .degrees
+                .get(&node.addr)
+                .ok_or_else(|| missing_metric("degree", node.addr))?
+                as usize;
+            node.eigenvector = *ips_state
+                .eigenvalues
+                .get(&node.addr)
+                .ok_or_else(|| missing_metric("eigenvector", node.addr))?;
+        }
 
-        ips_state.eigenvector_factors = NormalizationFactors::determine(
+        ips_state.degree_factors =
+            normalization_factors(&ips_state.degrees.values().cloned().collect::<Vec<u32>>())?;
+
+        ips_state.eigenvector_factors = normalization_factors(
             &ips_state
                 .eigenvalues
                 .values()
                 .cloned()
                 .collect::<Vec<f64>>(),
-        )
-        .expect("can't calculate eigenvector factors");
+        )?;
 
         let betweenness = &nodes.iter().map(|n| n.betweenness).collect::<Vec<f64>>();
-        ips_state.betweenness_factors = NormalizationFactors::determine(betweenness)
-            .expect("can't calculate betweenness factors");
+        ips_state.betweenness_factors = normalization_factors(betweenness)?;
 
         let closeness = &nodes.iter().map(|n| n.closeness).collect::<Vec<f64>>();
-        ips_state.closeness_factors =
-            NormalizationFactors::determine(closeness).expect("can't calculate closeness factors");
+        ips_state.closeness_factors = normalization_factors(closeness)?;
 
         ips_state.peer_list = Peer::generate_all_peerlists(nodes);
 
-        ips_state
+        Ok(ips_state)
     }
 
     /// Calculates const factors for each node.
@@ -555,117 +1171,267 @@ impl Ips {
         nodes: &[Node],
         ratings: &mut [PeerEntry],
     ) {
-        if selected_node.geolocation.is_none() {
+        let coordinates = |node: &Node| node.geolocation.as_ref().and_then(|g| g.coordinates);
+        let Some(selected_location) = coordinates(selected_node) else {
             return;
-        }
-
-        let selected_location =
-            if let Some(coordinates) = selected_node.geolocation.as_ref().unwrap().coordinates {
-                coordinates
-            } else {
-                return;
-            };
+        };
 
         for (node_idx, node) in nodes.iter().enumerate() {
-            if node.geolocation.is_none() {
+            let Some(location) = coordinates(node) else {
                 continue;
-            }
+            };
 
-            let geo_info = node.geolocation.as_ref().unwrap();
-            if geo_info.coordinates.is_none() {
-                continue;
-            }
+            let distance = selected_location.distance_to(location);
+            ratings[node_idx].rating +=
+                self.location_rating(distance) * self.config.mcda_weights.location;
+        }
+    }
 
-            let distance = selected_location.distance_to(geo_info.coordinates.unwrap());
-            let minmax_distance_m = self.config.geolocation_minmax_distance_km as f64 * 1000.0;
-
-            // Map distance to some levels of rating - now they are taken arbitrarily but
-            // they should be somehow related to the distance.
-            let rating = if self.config.geolocation == GeoLocationMode::PreferCloser {
-                match distance {
-                    _ if distance < minmax_distance_m => NORMALIZE_TO_VALUE,
-                    _ if distance < 2.0 * minmax_distance_m => NORMALIZE_2_3,
-                    _ if distance < 3.0 * minmax_distance_m => NORMALIZE_1_3,
-                    _ => 0.0,
-                }
-            } else {
-                match distance {
-                    _ if distance < 0.5 * minmax_distance_m => 0.0,
-                    _ if distance < minmax_distance_m => NORMALIZE_HALF,
-                    _ => NORMALIZE_TO_VALUE,
-                }
-            };
-            ratings[node_idx].rating += rating * self.config.mcda_weights.location;
+    /// Rating of the peer located at given distance (in meters) from the node.
+    fn location_rating(&self, distance: f64) -> f64 {
+        let minmax_distance_m = self.config.geolocation_minmax_distance_km as f64 * 1000.0;
+
+        // Map distance to some levels of rating - now they are taken arbitrarily but
+        // they should be somehow related to the distance.
+        if self.config.geolocation == GeoLocationMode::PreferCloser {
+            let ratings = &self.config.thresholds.closer_location_ratings;
+            match distance {
+                _ if distance < minmax_distance_m => ratings[0],
+                _ if distance < 2.0 * minmax_distance_m => ratings[1],
+                _ if distance < 3.0 * minmax_distance_m => ratings[2],
+                _ => ratings[3],
+            }
+        } else {
+            let ratings = &self.config.thresholds.distant_location_ratings;
+            match distance {
+                _ if distance < 0.5 * minmax_distance_m => ratings[0],
+                _ if distance < minmax_distance_m => ratings[1],
+                _ => ratings[2],
+            }
         }
     }
 
     fn rate_node(&self, node: &Node, state: &IpsState) -> f64 {
+        self.rating_breakdown(node, state).total()
+    }
+
+    /// Contributions of the node's graph params to its rating (without location).
+    fn rating_breakdown(&self, node: &Node, state: &IpsState) -> RatingBreakdown {
         // Calculate rating for node (if min == max for normalization factors then rating is
         // not increased for that factor as lerp() returns 0.0).
         // Rating is a combination of the following factors:
-        let mut rating = 0.0;
+        RatingBreakdown {
+            // 1. Degree
+            degree: state.degree_factors.scale(node.degree as f64)
+                * NORMALIZE_TO_VALUE
+                * self.config.mcda_weights.degree,
+            // 2. Betweenness
+            betweenness: state.betweenness_factors.scale(node.betweenness)
+                * NORMALIZE_TO_VALUE
+                * self.config.mcda_weights.betweenness,
+            // 3. Closeness
+            closeness: state.closeness_factors.scale(node.closeness)
+                * NORMALIZE_TO_VALUE
+                * self.config.mcda_weights.closeness,
+            // 4. Eigenvector
+            eigenvector: state.eigenvector_factors.scale(node.eigenvector)
+                * NORMALIZE_TO_VALUE
+                * self.config.mcda_weights.eigenvector,
+            // 5. Clustering coefficient
+            clustering: state.clustering_factors.scale(node.clustering)
+                * NORMALIZE_TO_VALUE
+                * self.config.mcda_weights.clustering,
+            location: 0.0,
+        }
+    }
+
+    /// Explain the final peer list of every node: compare it with the original one and attach
+    /// rating breakdown of every peer (as seen by the node) with the recorded reason.
+    fn explain_peer_lists(
+        &self,
+        initial_nodes: &[Node],
+        final_nodes: &[Node],
+        state: &IpsState,
+        desired_degrees: &[u32],
+        reasons: &[HashMap<usize, PeerReason>],
+    ) -> Vec<NodeExplanation> {
+        let breakdowns = state
+            .nodes
+            .iter()
+            .map(|node| self.rating_breakdown(node, state))
+            .collect::<Vec<_>>();
 
-        let addr = node.addr;
-        let degree = *state.degrees.get(&addr).expect(ERR_GET_DEGREE);
-        let eigenvalue = *state.eigenvalues.get(&addr).expect(ERR_GET_EIGENVECTOR);
+        let mut explanations = Vec::with_capacity(final_nodes.len());
+        for (node_idx, node) in state.nodes.iter().enumerate() {
+            let initial = initial_nodes[node_idx]
+                .connections
+                .iter()
+                .copied()
+                .filter(|peer| *peer != node_idx)
+                .collect::<HashSet<usize>>();
+            let current = final_nodes[node_idx]
+                .connections
+                .iter()
+                .copied()
+                .filter(|peer| *peer != node_idx)
+                .collect::<HashSet<usize>>();
+
+            let mut peers = initial.union(&current).copied().collect::<Vec<usize>>();
+            peers.sort_unstable();
+
+            let peers = peers
+                .into_iter()
+                .map(|peer_idx| {
+                    let action = match (initial.contains(&peer_idx), current.contains(&peer_idx)) {
+                        (true, true) => PeerAction::Kept,
+                        (false, _) => PeerAction::Added,
+                        (true, false) => PeerAction::Removed,
+                    };
+
+                    let peer = &state.nodes[peer_idx];
+                    let rating = RatingBreakdown {
+                        location: self.pair_location_rating(node, peer),
+                        ..breakdowns[peer_idx]
+                    };
+
+                    PeerExplanation {
+                        ip: peer.addr,
+                        action,
+                        reason: PeerReason::resolve(
+                            action,
+                            reasons[node_idx].get(&peer_idx).copied(),
+                        ),
+                        rating,
+                    }
+                })
+                .collect();
+
+            explanations.push(NodeExplanation {
+                ip: node.addr,
+                degree: initial.len(),
+                desired_degree: desired_degrees[node_idx],
+                peers,
+            });
+        }
 
-        // 1. Degree
-        rating += state.degree_factors.scale(degree as f64)
-            * NORMALIZE_TO_VALUE
-            * self.config.mcda_weights.degree;
+        explanations
+    }
 
-        // 2. Betweenness
-        rating += state.betweenness_factors.scale(node.betweenness)
-            * NORMALIZE_TO_VALUE
-            * self.config.mcda_weights.betweenness;
+    /// Describe islands of the partitioned network and suggest bridges between each pair of the
+    /// massive ones. Bridge candidates are the best rated nodes of both islands and pairs are
+    /// scored by both ratings plus the location rating of the pair.
+    fn generate_partition_report(
+        &self,
+        state: &IpsState,
+        islands: &[HashSet<usize>],
+        massive_island_size: usize,
+    ) -> PartitionReport {
+        let ratings = self.calculate_const_factors(state);
+
+        let mut report = PartitionReport::default();
+        let mut candidates = Vec::new();
+        for (id, island) in islands.iter().enumerate() {
+            let island = island.iter().copied().collect::<Vec<usize>>();
+            let massive = island.len() > massive_island_size;
+            report
+                .islands
+                .push(IslandReport::new(id, &island, &state.nodes, massive));
+
+            if massive {
+                let mut best = island;
+                best.sort_by(|a, b| {
+                    ratings[*b]
+                        .rating
+                        .total_cmp(&ratings[*a].rating)
+                        .then(a.cmp(b))
+                });
+                best.truncate(PARTITION_BRIDGE_CANDIDATES);
+                candidates.push((id, best));
+            }
+        }
 
-        // 3. Closeness
-        rating += state.closeness_factors.scale(node.closeness)
-            * NORMALIZE_TO_VALUE
-            * self.config.mcda_weights.closeness;
+        for (i, (island_a, nodes_a)) in candidates.iter().enumerate() {
+            for (island_b, nodes_b) in &candidates[i + 1..] {
+                let mut suggestions = Vec::new();
+                for a in nodes_a {
+                    for b in nodes_b {
+                        suggestions.push(BridgeSuggestion {
+                            islands: (*island_a, *island_b),
+                            peers: (state.nodes[*a].addr, state.nodes[*b].addr),
+                            score: ratings[*a].rating
+                                + ratings[*b].rating
+                                + self.pair_location_rating(&state.nodes[*a], &state.nodes[*b]),
+                        });
+                    }
+                }
 
-        // 4. Eigenvector
-        rating += state.eigenvector_factors.scale(eigenvalue)
-            * NORMALIZE_TO_VALUE
-            * self.config.mcda_weights.eigenvector;
+                suggestions.sort_by(|a, b| b.score.total_cmp(&a.score));
+                suggestions.truncate(PARTITION_BRIDGE_SUGGESTIONS);
+                report.bridge_suggestions.extend(suggestions);
+            }
+        }
 
-        rating
+        report
     }
 
-    // Very simple algorithm to detect islands.
-    // Take first vertex and do BFS to find all connected vertices. If there are any unvisited vertices
-    // create new island and do BFS one more time. Repeat until all vertices are visited.
-    fn detect_islands(&self, nodes: &[Node]) -> Vec<HashSet<usize>> {
-        let mut islands = Vec::new();
-        let mut visited = vec![false; nodes.len()];
+    /// Weighted location rating of two nodes (0 if location is not taken into account or any of
+    /// the nodes has no coordinates).
+    fn pair_location_rating(&self, a: &Node, b: &Node) -> f64 {
+        if self.config.geolocation == GeoLocationMode::Off {
+            return 0.0;
+        }
 
-        for i in 0..nodes.len() {
-            if visited[i] {
-                continue;
+        let coordinates = |node: &Node| node.geolocation.as_ref().and_then(|g| g.coordinates);
+        match (coordinates(a), coordinates(b)) {
+            (Some(a), Some(b)) => {
+                self.location_rating(a.distance_to(b)) * self.config.mcda_weights.location
             }
+            _ => 0.0,
+        }
+    }
 
-            let mut island = HashSet::new();
-            let mut queue = VecDeque::new();
-            queue.push_back(i);
-
-            while let Some(node_idx) = queue.pop_front() {
-                if visited[node_idx] {
-                    continue;
-                }
+    /// Log islands and suggested bridges of the partition report.
+    fn print_partition_report(
+        &self,
+        o: &mut Box<dyn Write>,
+        report: &PartitionReport,
+    ) -> io::Result<()> {
+        for island in &report.islands {
+            writeln!(
+                o,
+                "Island {}{}: {} nodes ({:.2}% of the network), {} geolocated in {} countries, spread {:.0} km",
+                island.id,
+                if island.massive { " (massive)" } else { "" },
+                island.size,
+                island.share * 100.0,
+                island.geolocated,
+                island.countries.len(),
+                island.spread_km
+            )
+            ?;
+        }
 
-                island.insert(node_idx);
+        for suggestion in &report.bridge_suggestions {
+            writeln!(
+                o,
+                "Suggested bridge between islands {} and {}: {} - {} (score {:.3})",
+                suggestion.islands.0,
+                suggestion.islands.1,
+                suggestion.peers.0,
+                suggestion.peers.1,
+                suggestion.score
+            )?;
+        }
 
-                visited[node_idx] = true;
+        Ok(())
+    }
 
-                for j in 0..nodes[node_idx].connections.len() {
-                    if !visited[nodes[node_idx].connections[j]] {
-                        queue.push_back(nodes[node_idx].connections[j]);
-                    }
-                }
-            }
-            islands.push(island);
-        }
-        islands
+    /// Detect islands - connected components of the network.
+    fn detect_islands(&self, nodes: &[Node]) -> Vec<HashSet<usize>> {
+        detect_components(nodes)
+            .into_iter()
+            .map(|component| component.into_iter().collect())
+            .collect()
     }
 }
 
@@ -680,13 +1446,19 @@ mod tests {
     use spectre::{edge::Edge, graph::Graph};
 
     use super::*;
+    use crate::{
+        ips::config::{
+            ConnectionLimits, DesiredDegreePolicy, DiversityConstraint, NetworkConnectionLimits,
+        },
+        nodes::subnet,
+    };
 
     pub const ERR_PARSE_IP: &str = "failed to parse IP address";
 
     #[test]
     fn rate_node_test() {
         let ips_config = IPSConfiguration::default();
-        let ips = Ips::new(ips_config);
+        let ips = Ips::new(ips_config, AnalysisConfiguration::default());
 
         let nodes = vec![
             Node {
@@ -707,9 +1479,299 @@ mod tests {
         ];
 
         let num_threads = thread::available_parallelism().unwrap().get();
-        let state = ips.generate_state(&nodes, true, num_threads);
+        let state = ips.generate_state(&nodes, true, num_threads).unwrap();
 
-        assert_eq!(ips.rate_node(nodes.get(0).unwrap(), &state), 10.0);
+        assert_eq!(ips.rate_node(&state.nodes[0], &state), 10.0);
+    }
+
+    #[tokio::test]
+    async fn generate_keeps_bridges_test() {
+        // Clique of 9 nodes with a leaf hanging on node 0 - the leaf is the lowest rated peer
+        // of node 0 and their connection is the only bridge.
+        let mut nodes = (0..10)
+            .map(|i| Node {
+                addr: SocketAddr::new(
+                    IpAddr::from_str(format!("192.169.0.{i}").as_str()).expect(ERR_PARSE_IP),
+                    1234,
+                ),
+                network_type: NetworkType::Zcash,
+                ..Default::default()
+            })
+            .collect::<Vec<Node>>();
+        for (i, node) in nodes.iter_mut().enumerate().take(9) {
+            node.connections = (0..9).filter(|j| *j != i).collect();
+        }
+        nodes[0].connections.push(9);
+        nodes[9].connections.push(0);
+
+        let state = CrunchyState {
+            nodes,
+            ..Default::default()
+        };
+        let mut ips = Ips::new(
+            IPSConfiguration {
+                bridge_detection: BridgeDetectionMode::Exact,
+                ..Default::default()
+            },
+            AnalysisConfiguration::default(),
+        );
+
+        let peers = ips.generate(&state, NetworkType::Zcash, 1).await.unwrap();
+        let (hub, leaf) = (state.nodes[0].addr, state.nodes[9].addr);
+        assert!(peers[0].list.contains(&leaf));
+        assert!(peers[9].list.contains(&hub));
+    }
+
+    #[tokio::test]
+    async fn generate_unchanged_network_test() {
+        // Ring of 6 nodes which IPS is not allowed to change.
+        let nodes = (0..6)
+            .map(|i| Node {
+                addr: SocketAddr::new(
+                    IpAddr::from_str(format!("192.169.0.{i}").as_str()).expect(ERR_PARSE_IP),
+                    1234,
+                ),
+                network_type: NetworkType::Zcash,
+                connections: vec![(i + 5) % 6, (i + 1) % 6],
+                ..Default::default()
+            })
+            .collect::<Vec<Node>>();
+
+        let state = CrunchyState {
+            nodes,
+            ..Default::default()
+        };
+        let mut ips = Ips::new(
+            IPSConfiguration {
+                change_at_least: 0,
+                change_no_more: 0,
+                reject_lower_connectivity: true,
+                ..Default::default()
+            },
+            AnalysisConfiguration::default(),
+        );
+
+        let peers = ips.generate(&state, NetworkType::Zcash, 1).await.unwrap();
+        let original = Peer::generate_all_peerlists(&state.nodes);
+        assert_eq!(peers.len(), original.len());
+        for (peer, original) in peers.iter().zip(&original) {
+            assert_eq!(peer.ip, original.ip);
+            assert_eq!(peer.list, original.list);
+        }
+
+        // Noise of the eigenvalue computation doesn't reject the result, a real drop does.
+        assert!(!lowers_connectivity(0.5, 0.5 * (1.0 - 1e-9)));
+        assert!(!lowers_connectivity(0.0, 0.0));
+        assert!(lowers_connectivity(0.5, 0.49));
+    }
+
+    #[test]
+    fn generate_state_eclipse_risk_test() {
+        let nodes = (0..6)
+            .map(|i| Node {
+                addr: SocketAddr::new(
+                    IpAddr::from_str(format!("192.169.0.{i}").as_str()).expect(ERR_PARSE_IP),
+                    1234,
+                ),
+                connections: vec![(i + 5) % 6, (i + 1) % 6],
+                eclipse_risk: Some(0.5),
+                ..Default::default()
+            })
+            .collect::<Vec<Node>>();
+
+        // Risk is not computed unless nodes are prioritized by it.
+        let ips = Ips::new(
+            IPSConfiguration::default(),
+            AnalysisConfiguration::default(),
+        );
+        let state = ips.generate_state(&nodes, true, 1).unwrap();
+        assert!(state.eclipse.is_none());
+        assert!(state.nodes.iter().all(|node| node.eclipse_risk.is_none()));
+
+        let ips = Ips::new(
+            IPSConfiguration {
+                prioritize_eclipse_risk: true,
+                ..Default::default()
+            },
+            AnalysisConfiguration::default(),
+        );
+        let state = ips.generate_state(&nodes, true, 1).unwrap();
+        assert!(state.eclipse.is_some());
+        assert!(state.nodes.iter().all(|node| node.eclipse_risk.is_some()));
+    }
+
+    #[tokio::test]
+    async fn generate_over_limit_node_test() {
+        // Hub 0 connected to the ring of nodes 1 - 5 has more peers than its outbound slots.
+        let mut connections = vec![Vec::new(); 6];
+        let mut connect = |a: usize, b: usize| {
+            connections[a].push(b);
+            connections[b].push(a);
+        };
+        for i in 1..6 {
+            connect(0, i);
+            connect(i, i % 5 + 1);
+        }
+        let nodes = connections
+            .into_iter()
+            .enumerate()
+            .map(|(i, connections)| Node {
+                addr: SocketAddr::new(
+                    IpAddr::from_str(format!("192.169.0.{i}").as_str()).expect(ERR_PARSE_IP),
+                    1234,
+                ),
+                network_type: NetworkType::Zcash,
+                connections,
+                ..Default::default()
+            })
+            .collect::<Vec<Node>>();
+
+        let state = CrunchyState {
+            nodes,
+            ..Default::default()
+        };
+        let max_outbound = 3;
+        let mut ips = Ips::new(
+            IPSConfiguration {
+                desired_degree: DesiredDegreePolicy::Fixed(3),
+                change_no_more: 2,
+                connection_limits: vec![NetworkConnectionLimits {
+                    network: NetworkType::Zcash,
+                    limits: ConnectionLimits {
+                        max_outbound: Some(max_outbound),
+                        max_inbound: None,
+                        max_total: Some(max_outbound),
+                    },
+                }],
+                ..Default::default()
+            },
+            AnalysisConfiguration::default(),
+        );
+
+        // The hub has no room for new peers, but still sheds the peers over the limit.
+        let peers = ips.generate(&state, NetworkType::Zcash, 1).await.unwrap();
+        assert_eq!(peers[0].ip, state.nodes[0].addr);
+        assert!(peers[0].list.len() <= max_outbound);
+    }
+
+    #[tokio::test]
+    async fn generate_diversity_both_ends_test() {
+        // Hub 0 sheds its peers, so it becomes a candidate of the nodes looking for more peers.
+        // Hub and nodes 1, 7, 8 and 9 are in 10.1.0.0/16, the rest in distinct subnets.
+        let subnets = [1, 1, 3, 4, 5, 6, 7, 1, 1, 1];
+        let mut connections = vec![Vec::new(); 10];
+        let mut connect = |a: usize, b: usize| {
+            connections[a].push(b);
+            connections[b].push(a);
+        };
+        for i in 1..7 {
+            connect(0, i);
+        }
+        for i in 2..7 {
+            connect(1, i);
+        }
+        for i in 0..3 {
+            connect(7 + i, 2 + i);
+        }
+        let nodes = connections
+            .into_iter()
+            .enumerate()
+            .map(|(i, connections)| Node {
+                addr: SocketAddr::new(
+                    IpAddr::from_str(format!("10.{}.0.{i}", subnets[i]).as_str())
+                        .expect(ERR_PARSE_IP),
+                    1234,
+                ),
+                network_type: NetworkType::Zcash,
+                connections,
+                ..Default::default()
+            })
+            .collect::<Vec<Node>>();
+
+        let state = CrunchyState {
+            nodes,
+            ..Default::default()
+        };
+        let mut ips = Ips::new(
+            IPSConfiguration {
+                desired_degree: DesiredDegreePolicy::Fixed(3),
+                change_no_more: 4,
+                diversity: DiversityConstraint {
+                    max_peers_per_subnet: Some(1),
+                    max_peers_per_country: None,
+                },
+                ..Default::default()
+            },
+            AnalysisConfiguration::default(),
+        );
+
+        // Subnets over the limit in the original lists can't grow any further.
+        let subnet_counts = |list: &[SocketAddr]| {
+            let mut counts = HashMap::new();
+            for addr in list {
+                *counts.entry(subnet(addr.ip())).or_insert(0) += 1;
+            }
+            counts
+        };
+        let original = Peer::generate_all_peerlists(&state.nodes);
+        let peers = ips.generate(&state, NetworkType::Zcash, 1).await.unwrap();
+        for (peer, original) in peers.iter().zip(&original) {
+            let original_counts = subnet_counts(&original.list);
+            for (subnet, count) in subnet_counts(&peer.list) {
+                let original_count = original_counts.get(&subnet).copied().unwrap_or_default();
+                assert!(count <= original_count.max(1), "{} {subnet}", peer.ip);
+            }
+        }
+    }
+
+    #[test]
+    fn check_integrity_partitioned_test() {
+        // Two islands: path 0 - ... - 9 and path 10 - ... - 19. The hottest nodes are the middle
+        // of the first island and the leaf 19 which has a single neighbor.
+        let mut nodes = (0..20)
+            .map(|i| Node {
+                addr: SocketAddr::new(
+                    IpAddr::from_str(format!("192.169.0.{i}").as_str()).expect(ERR_PARSE_IP),
+                    1234,
+                ),
+                ..Default::default()
+            })
+            .collect::<Vec<Node>>();
+        for i in (0..9).chain(10..19) {
+            nodes[i].connections.push(i + 1);
+            nodes[i + 1].connections.push(i);
+        }
+        nodes[4].betweenness = 2.0;
+        nodes[19].betweenness = 1.0;
+
+        let ips = Ips::new(
+            IPSConfiguration::default(),
+            AnalysisConfiguration::default(),
+        );
+        let mut state = IpsState {
+            nodes,
+            ..Default::default()
+        };
+
+        // Removal splits the first island, its halves get connected.
+        assert!(!ips.check_and_fix_integrity_upon_removal(&mut state));
+        assert!(state.nodes[3].connections.contains(&5));
+        assert!(state.nodes[5].connections.contains(&3));
+
+        // The leaf has nothing to connect and islands stay separated.
+        assert_eq!(state.nodes[18].connections, vec![17, 19]);
+        for (idx, node) in state.nodes.iter().enumerate() {
+            assert!(node
+                .connections
+                .iter()
+                .all(|peer| (*peer < 10) == (idx < 10)));
+        }
+
+        // Partition itself is not a reason to fix anything.
+        state.nodes[4].betweenness = 0.0;
+        state.nodes[19].betweenness = 2.0;
+        state.nodes[9].betweenness = 1.0;
+        assert!(ips.check_and_fix_integrity_upon_removal(&mut state));
     }
 
     #[tokio::test]
@@ -718,7 +1780,7 @@ mod tests {
         let mut nodes = Vec::new();
         let mut addrs = Vec::new();
         let ips_config = IPSConfiguration::default();
-        let ips = Ips::new(ips_config);
+        let ips = Ips::new(ips_config, AnalysisConfiguration::default());
 
         for i in 0..10 {
             let addr = SocketAddr::new(
@@ -758,7 +1820,7 @@ mod tests {
         let mut nodes = Vec::new();
         let mut addrs = Vec::new();
         let ips_config = IPSConfiguration::default();
-        let ips = Ips::new(ips_config);
+        let ips = Ips::new(ips_config, AnalysisConfiguration::default());
 
         for i in 0..10 {
             let addr = SocketAddr::new(
@@ -792,4 +1854,59 @@ mod tests {
 
         assert_eq!(islands.len(), nodes.len());
     }
+
+    #[tokio::test]
+    async fn generate_partitioned_test() {
+        // Two separate cliques of 6 nodes - both are massive islands.
+        let mut nodes = (0..12)
+            .map(|i| Node {
+                addr: SocketAddr::new(
+                    IpAddr::from_str(format!("192.169.0.{i}").as_str()).expect(ERR_PARSE_IP),
+                    1234,
+                ),
+                network_type: NetworkType::Zcash,
+                ..Default::default()
+            })
+            .collect::<Vec<Node>>();
+        for (i, node) in nodes.iter_mut().enumerate() {
+            let island = i / 6 * 6;
+            node.connections = (island..island + 6).filter(|j| *j != i).collect();
+        }
+
+        let state = CrunchyState {
+            nodes,
+            ..Default::default()
+        };
+        let mut ips = Ips::new(
+            IPSConfiguration::default(),
+            AnalysisConfiguration::default(),
+        );
+
+        let peers = ips.generate(&state, NetworkType::Zcash, 1).await.unwrap();
+        assert_eq!(peers.len(), 12);
+        for (i, peer) in peers.iter().enumerate() {
+            let island = &state.nodes[i / 6 * 6..i / 6 * 6 + 6];
+            assert!(peer
+                .list
+                .iter()
+                .all(|addr| island.iter().any(|node| node.addr == *addr)));
+        }
+
+        let islands = ips.detect_islands(&state.nodes);
+        let report = ips.generate_partition_report(
+            &ips.generate_state(&state.nodes, true, 1).unwrap(),
+            &islands,
+            1,
+        );
+        assert_eq!(report.islands.len(), 2);
+        assert!(report.islands.iter().all(|island| island.massive));
+        assert_eq!(
+            report.bridge_suggestions.len(),
+            PARTITION_BRIDGE_SUGGESTIONS
+        );
+        assert!(report
+            .bridge_suggestions
+            .windows(2)
+            .all(|pair| pair[0].score >= pair[1].score));
+    }
 }
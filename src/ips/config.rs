@@ -1,8 +1,23 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use serde::Deserialize;
+use ziggurat_core_crawler::summary::NetworkType;
 
-use crate::config::GeoLocationMode;
+use crate::{
+    config::GeoLocationMode,
+    error::{CrunchyError, Result},
+};
+
+/// Location rating is between 0 and this value
+pub const LOCATION_RATING_MAX: f64 = 100.0;
+/// Default share of the network nodes an island must exceed to be considered massive
+pub const DEFAULT_MASSIVE_ISLAND_PERCENTAGE: f64 = 0.1;
+/// Default share of the highest betweenness nodes removed by the fragmentation check
+pub const DEFAULT_NODES_TO_BE_REMOVED_PERCENTAGE: f64 = 0.1;
+/// Default number of candidates considered for each peer to be added
+pub const DEFAULT_CANDIDATE_MULTIPLIER: u32 = 2;
+/// Default number of simulated IPS rounds
+pub const DEFAULT_SIMULATION_ROUNDS: usize = 10;
 
 /// Multi-criteria analysis weights
 #[derive(Debug, Clone, Deserialize)]
@@ -17,6 +32,107 @@ pub struct MultiCriteriaAnalysisWeights {
     pub betweenness: f64,
     /// Weight (importance) of the closeness factor
     pub closeness: f64,
+    /// Weight (importance) of the clustering coefficient factor
+    #[serde(default)]
+    pub clustering: f64,
+}
+
+/// Safety thresholds and rating levels used by the algorithm
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct IPSThresholds {
+    /// Island bigger than this share of the network nodes is considered massive - massive
+    /// islands are never merged, peer lists are generated within each of them
+    pub massive_island_percentage: f64,
+    /// Share of the highest betweenness nodes removed to check if the network can be fragmented
+    pub nodes_to_be_removed_percentage: f64,
+    /// Number of candidates considered for each peer to be added - candidates are taken by
+    /// rating and those with the lowest betweenness are chosen
+    pub candidate_multiplier: u32,
+    /// Location ratings used when closer peers are preferred, for distance below 1, 2 and 3
+    /// times `geolocation_minmax_distance_km` and above it
+    pub closer_location_ratings: [f64; 4],
+    /// Location ratings used when distant peers are preferred, for distance below 0.5 and 1
+    /// times `geolocation_minmax_distance_km` and above it
+    pub distant_location_ratings: [f64; 3],
+}
+
+/// Limits on the number of peers sharing the same netgroup - a node with all peers in a single
+/// subnet or country is easy to eclipse
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct DiversityConstraint {
+    /// Maximum number of peers in the same IPv4 /16 or IPv6 /32 subnet (unlimited if not set)
+    pub max_peers_per_subnet: Option<usize>,
+    /// Maximum number of peers in the same country (unlimited if not set, nodes without known
+    /// country are not limited)
+    pub max_peers_per_country: Option<usize>,
+}
+
+/// Multi-round IPS simulation parameters
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct SimulationConfiguration {
+    /// Number of IPS rounds, each one run over the network produced by the previous one
+    pub rounds: usize,
+    /// Probability that a node accepts its new peer list in a round
+    pub adoption_probability: f64,
+    /// Seed for choosing the adopting nodes, so results can be reproduced
+    pub seed: u64,
+}
+
+/// DesiredDegreePolicy enum - indicates how many peers each node should pursue.
+#[derive(Debug, Default, PartialEq, Clone, Copy, Deserialize)]
+pub enum DesiredDegreePolicy {
+    /// Network mean degree
+    #[default]
+    Mean,
+    /// Network median degree
+    Median,
+    /// Given percentile (0-100) of the network degrees
+    Percentile(f64),
+    /// Fixed number of peers
+    Fixed(u32),
+    /// Current degree of the node clamped to the range
+    Clamped { min: u32, max: u32 },
+    /// Current degree of the node moved by the given share (0-1) of its distance to the network
+    /// mean degree
+    TowardMean(f64),
+}
+
+/// Connection slots of the nodes - IPS never recommends more peers than a node can open or
+/// accept
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ConnectionLimits {
+    /// Maximum number of outbound connections - peers a node opens itself (unlimited if not set)
+    pub max_outbound: Option<usize>,
+    /// Maximum number of inbound connections - peers connecting to a node (unlimited if not set)
+    pub max_inbound: Option<usize>,
+    /// Maximum number of all connections of a node (unlimited if not set)
+    pub max_total: Option<usize>,
+}
+
+/// Connection limits of the nodes of a single network
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct NetworkConnectionLimits {
+    /// Network the limits apply to
+    pub network: NetworkType,
+    /// The limits
+    #[serde(flatten)]
+    pub limits: ConnectionLimits,
+}
+
+/// BridgeDetectionMode enum - indicates which connections are protected from removal as bridges.
+#[derive(Debug, Default, PartialEq, Clone, Copy, Deserialize)]
+pub enum BridgeDetectionMode {
+    /// Connections between nodes with betweenness above the adjusted median
+    #[default]
+    Heuristic,
+    /// True bridges found with Tarjan's algorithm
+    Exact,
+    /// Connections found by any of the above
+    Union,
 }
 
 /// Configuration for Intelligent Peer Sharing module
@@ -37,10 +153,58 @@ pub struct IPSConfiguration {
     pub change_no_more: u32,
     /// Indicates adjustment factor for bridge detection
     pub bridge_threshold_adjustment: f64,
+    /// Indicates how bridges are detected (heuristic if not set)
+    #[serde(default)]
+    pub bridge_detection: BridgeDetectionMode,
+    /// Number of peers each node should pursue (network mean if not set)
+    #[serde(default)]
+    pub desired_degree: DesiredDegreePolicy,
     /// Multi-criteria analysis weights
     pub mcda_weights: MultiCriteriaAnalysisWeights,
+    /// Safety thresholds and rating levels (defaults if not set)
+    #[serde(default)]
+    pub thresholds: IPSThresholds,
+    /// Limits on the number of peers from the same subnet or country (no limits if not set)
+    #[serde(default)]
+    pub diversity: DiversityConstraint,
+    /// Connection limits for each network (no limits for networks not listed)
+    #[serde(default)]
+    pub connection_limits: Vec<NetworkConnectionLimits>,
+    /// If set, nodes from the network periphery prefer new peers from the network core
+    #[serde(default)]
+    pub prefer_core_connections: bool,
+    /// If set, nodes with the highest eclipse risk get new peers first
+    #[serde(default)]
+    pub prioritize_eclipse_risk: bool,
+    /// If set, peer lists lowering algebraic connectivity of the network are rejected
+    #[serde(default)]
+    pub reject_lower_connectivity: bool,
+    /// If set, nodes without connections to other communities look for new peers there first
+    /// and their last connection to another community is never removed
+    #[serde(default)]
+    pub inter_community_links: bool,
     /// If set, vanilla (original, before IPS) peer list should be generated in the specified file
     pub vanilla_peer_file_path: Option<PathBuf>,
+    /// If set, the partition report is written there when the network is split into more than
+    /// one massive island
+    pub partition_report_path: Option<PathBuf>,
+    /// If set, explanation of every peer list entry (rating breakdown and reason) is written
+    /// there
+    pub explanation_file_path: Option<PathBuf>,
+    /// If set, peers to be added and removed for every node (relative to the crawled
+    /// connections) and aggregate churn are written there
+    pub delta_file_path: Option<PathBuf>,
+    /// Multi-round simulation parameters (defaults if not set)
+    #[serde(default)]
+    pub simulation: SimulationConfiguration,
+    /// If set, statistics of every simulated round are written there by the simulate command
+    pub simulation_file_path: Option<PathBuf>,
+    /// Network IPS should work on (if none, Zcash is assumed)
+    pub network: Option<NetworkType>,
+    /// If set, IPS is run once for every network found in the sample and each network gets its
+    /// own peer file (network name is appended to the configured file names)
+    #[serde(default)]
+    pub all_networks: bool,
 }
 
 impl Default for IPSConfiguration {
@@ -52,9 +216,293 @@ impl Default for IPSConfiguration {
             geolocation_minmax_distance_km: 1000,
             change_at_least: 1,
             change_no_more: 2,
+            desired_degree: DesiredDegreePolicy::Mean,
             mcda_weights: MultiCriteriaAnalysisWeights::default(),
+            thresholds: IPSThresholds::default(),
+            diversity: DiversityConstraint::default(),
+            connection_limits: Vec::new(),
             bridge_threshold_adjustment: 1.25,
+            bridge_detection: BridgeDetectionMode::Heuristic,
+            prefer_core_connections: false,
+            prioritize_eclipse_risk: false,
+            reject_lower_connectivity: false,
+            inter_community_links: false,
             vanilla_peer_file_path: None,
+            partition_report_path: None,
+            explanation_file_path: None,
+            delta_file_path: None,
+            simulation: SimulationConfiguration::default(),
+            simulation_file_path: None,
+            network: None,
+            all_networks: false,
+        }
+    }
+}
+
+impl IPSConfiguration {
+    /// Check if values are in allowed ranges.
+    pub fn validate(&self) -> Result<()> {
+        self.thresholds.validate()?;
+        self.desired_degree.validate()?;
+        self.diversity.validate()?;
+        self.simulation.validate()?;
+
+        for (idx, entry) in self.connection_limits.iter().enumerate() {
+            if self.connection_limits[..idx]
+                .iter()
+                .any(|other| other.network == entry.network)
+            {
+                return Err(CrunchyError::Config(format!(
+                    "connection limits for {:?} are set more than once",
+                    entry.network
+                )));
+            }
+            entry.limits.validate()?;
+        }
+
+        Ok(())
+    }
+
+    /// Connection limits of the network IPS works on.
+    pub fn connection_limits(&self) -> ConnectionLimits {
+        let network = self.network();
+        self.connection_limits
+            .iter()
+            .find(|entry| entry.network == network)
+            .map(|entry| entry.limits.clone())
+            .unwrap_or_default()
+    }
+
+    /// Network IPS should work on when not running over all networks.
+    pub fn network(&self) -> NetworkType {
+        self.network.unwrap_or(NetworkType::Zcash)
+    }
+
+    /// Create configuration copy with all output paths suffixed with the network name, so
+    /// results for different networks don't overwrite each other.
+    pub fn for_network(&self, network: NetworkType) -> IPSConfiguration {
+        let mut config = self.clone();
+        config.network = Some(network);
+        config.peer_file_path = self
+            .peer_file_path
+            .as_ref()
+            .map(|path| network_file_path(path, network));
+        config.log_path = self
+            .log_path
+            .as_ref()
+            .map(|path| network_file_path(path, network));
+        config.vanilla_peer_file_path = self
+            .vanilla_peer_file_path
+            .as_ref()
+            .map(|path| network_file_path(path, network));
+        config.partition_report_path = self
+            .partition_report_path
+            .as_ref()
+            .map(|path| network_file_path(path, network));
+        config.explanation_file_path = self
+            .explanation_file_path
+            .as_ref()
+            .map(|path| network_file_path(path, network));
+        config.delta_file_path = self
+            .delta_file_path
+            .as_ref()
+            .map(|path| network_file_path(path, network));
+        config.simulation_file_path = self
+            .simulation_file_path
+            .as_ref()
+            .map(|path| network_file_path(path, network));
+        config
+    }
+}
+
+/// Append network name to the file name, eg. `peers.json` becomes `peers-zcash.json`.
+pub fn network_file_path(path: &Path, network: NetworkType) -> PathBuf {
+    let network_name = format!("{network:?}").to_lowercase();
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let file_name = match path.extension() {
+        Some(ext) => format!("{stem}-{network_name}.{}", ext.to_string_lossy()),
+        None => format!("{stem}-{network_name}"),
+    };
+
+    path.with_file_name(file_name)
+}
+
+impl IPSThresholds {
+    /// Check if values are in allowed ranges.
+    pub fn validate(&self) -> Result<()> {
+        for (name, value) in [
+            ("massive_island_percentage", self.massive_island_percentage),
+            (
+                "nodes_to_be_removed_percentage",
+                self.nodes_to_be_removed_percentage,
+            ),
+        ] {
+            if value <= 0.0 || value > 1.0 {
+                return Err(CrunchyError::Config(format!(
+                    "{name} must be between 0 (exclusive) and 1, got {value}"
+                )));
+            }
+        }
+
+        if self.candidate_multiplier == 0 {
+            return Err(CrunchyError::Config(
+                "candidate_multiplier must be greater than 0".to_owned(),
+            ));
+        }
+
+        if self
+            .closer_location_ratings
+            .iter()
+            .chain(&self.distant_location_ratings)
+            .any(|rating| !(0.0..=LOCATION_RATING_MAX).contains(rating))
+        {
+            return Err(CrunchyError::Config(format!(
+                "location ratings must be between 0 and {LOCATION_RATING_MAX}"
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+impl DesiredDegreePolicy {
+    /// Check if values are in allowed ranges.
+    pub fn validate(&self) -> Result<()> {
+        let valid = match *self {
+            DesiredDegreePolicy::Mean | DesiredDegreePolicy::Median => true,
+            DesiredDegreePolicy::Percentile(percentile) => (0.0..=100.0).contains(&percentile),
+            DesiredDegreePolicy::Fixed(degree) => degree > 0,
+            DesiredDegreePolicy::Clamped { min, max } => min <= max && max > 0,
+            DesiredDegreePolicy::TowardMean(share) => (0.0..=1.0).contains(&share),
+        };
+
+        if !valid {
+            return Err(CrunchyError::Config(format!(
+                "invalid desired degree policy {self:?}: percentile must be between 0 and 100, \
+                 share between 0 and 1, fixed degree and range greater than 0"
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+impl DiversityConstraint {
+    /// Check if values are in allowed ranges.
+    pub fn validate(&self) -> Result<()> {
+        for (name, value) in [
+            ("max_peers_per_subnet", self.max_peers_per_subnet),
+            ("max_peers_per_country", self.max_peers_per_country),
+        ] {
+            if value == Some(0) {
+                return Err(CrunchyError::Config(format!(
+                    "{name} must be greater than 0"
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check if any limit is set.
+    pub fn is_enabled(&self) -> bool {
+        self.max_peers_per_subnet.is_some() || self.max_peers_per_country.is_some()
+    }
+}
+
+impl ConnectionLimits {
+    /// Check if values are in allowed ranges.
+    pub fn validate(&self) -> Result<()> {
+        for (name, value) in [
+            ("max_outbound", self.max_outbound),
+            ("max_inbound", self.max_inbound),
+            ("max_total", self.max_total),
+        ] {
+            if value == Some(0) {
+                return Err(CrunchyError::Config(format!(
+                    "{name} must be greater than 0"
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check if any limit is set.
+    pub fn is_enabled(&self) -> bool {
+        self.max_outbound.is_some() || self.max_inbound.is_some() || self.max_total.is_some()
+    }
+
+    /// Number of connections a node with the given number of peers can still open. Peers are
+    /// assumed to take the outbound slots first.
+    pub fn outbound_room(&self, degree: usize) -> usize {
+        let outbound = self.max_outbound.map_or(usize::MAX, |max_outbound| {
+            max_outbound - degree.min(max_outbound)
+        });
+        let total = self
+            .max_total
+            .map_or(usize::MAX, |max_total| max_total.saturating_sub(degree));
+        outbound.min(total)
+    }
+
+    /// Check if a node with the given number of peers can accept one more connection. Direction
+    /// of the crawled connections is unknown, so the node is assumed to fill its outbound slots
+    /// first and the rest of its peers count as inbound.
+    pub fn accepts_inbound(&self, degree: usize) -> bool {
+        let inbound = degree.saturating_sub(self.max_outbound.unwrap_or(0));
+        self.max_inbound
+            .is_none_or(|max_inbound| inbound < max_inbound)
+            && self.max_total.is_none_or(|max_total| degree < max_total)
+    }
+}
+
+impl SimulationConfiguration {
+    /// Check if values are in allowed ranges.
+    pub fn validate(&self) -> Result<()> {
+        if self.rounds == 0 {
+            return Err(CrunchyError::Config(
+                "simulation rounds must be greater than 0".to_owned(),
+            ));
+        }
+
+        if self.adoption_probability <= 0.0 || self.adoption_probability > 1.0 {
+            return Err(CrunchyError::Config(format!(
+                "adoption_probability must be between 0 (exclusive) and 1, got {}",
+                self.adoption_probability
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for SimulationConfiguration {
+    fn default() -> SimulationConfiguration {
+        SimulationConfiguration {
+            rounds: DEFAULT_SIMULATION_ROUNDS,
+            adoption_probability: 1.0,
+            seed: 0,
+        }
+    }
+}
+
+impl Default for IPSThresholds {
+    fn default() -> IPSThresholds {
+        IPSThresholds {
+            massive_island_percentage: DEFAULT_MASSIVE_ISLAND_PERCENTAGE,
+            nodes_to_be_removed_percentage: DEFAULT_NODES_TO_BE_REMOVED_PERCENTAGE,
+            candidate_multiplier: DEFAULT_CANDIDATE_MULTIPLIER,
+            closer_location_ratings: [
+                LOCATION_RATING_MAX,
+                LOCATION_RATING_MAX * 2.0 / 3.0,
+                LOCATION_RATING_MAX / 3.0,
+                0.0,
+            ],
+            distant_location_ratings: [0.0, LOCATION_RATING_MAX / 2.0, LOCATION_RATING_MAX],
         }
     }
 }
@@ -67,6 +515,163 @@ impl Default for MultiCriteriaAnalysisWeights {
             eigenvector: 0.1,
             betweenness: 0.25,
             closeness: 0.1,
+            clustering: 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn thresholds_validate_test() {
+        assert!(IPSThresholds::default().validate().is_ok());
+
+        let thresholds = IPSThresholds {
+            massive_island_percentage: 0.0,
+            ..Default::default()
+        };
+        assert!(thresholds.validate().is_err());
+
+        let thresholds = IPSThresholds {
+            candidate_multiplier: 0,
+            ..Default::default()
+        };
+        assert!(thresholds.validate().is_err());
+
+        let thresholds = IPSThresholds {
+            distant_location_ratings: [0.0, 50.0, 150.0],
+            ..Default::default()
+        };
+        assert!(thresholds.validate().is_err());
+    }
+
+    #[test]
+    fn desired_degree_policy_test() {
+        #[derive(Deserialize)]
+        struct Policy {
+            desired_degree: DesiredDegreePolicy,
         }
+        let parse = |policy: &str| {
+            toml::from_str::<Policy>(&format!("desired_degree = {policy}"))
+                .unwrap()
+                .desired_degree
+        };
+
+        assert_eq!(parse("\"Median\""), DesiredDegreePolicy::Median);
+        assert_eq!(
+            parse("{ Percentile = 75 }"),
+            DesiredDegreePolicy::Percentile(75.0)
+        );
+        assert_eq!(
+            parse("{ Clamped = { min = 4, max = 12 } }"),
+            DesiredDegreePolicy::Clamped { min: 4, max: 12 }
+        );
+        assert!(parse("{ TowardMean = 0.5 }").validate().is_ok());
+
+        assert!(DesiredDegreePolicy::Percentile(101.0).validate().is_err());
+        assert!(DesiredDegreePolicy::Fixed(0).validate().is_err());
+        assert!(DesiredDegreePolicy::Clamped { min: 8, max: 4 }
+            .validate()
+            .is_err());
+        assert!(DesiredDegreePolicy::TowardMean(1.5).validate().is_err());
+    }
+
+    #[test]
+    fn simulation_validate_test() {
+        assert!(SimulationConfiguration::default().validate().is_ok());
+
+        let simulation = SimulationConfiguration {
+            rounds: 0,
+            ..Default::default()
+        };
+        assert!(simulation.validate().is_err());
+
+        let simulation = SimulationConfiguration {
+            adoption_probability: 0.0,
+            ..Default::default()
+        };
+        assert!(simulation.validate().is_err());
+    }
+
+    #[test]
+    fn diversity_validate_test() {
+        assert!(DiversityConstraint::default().validate().is_ok());
+
+        let diversity = DiversityConstraint {
+            max_peers_per_subnet: Some(2),
+            max_peers_per_country: Some(0),
+        };
+        assert!(diversity.validate().is_err());
+    }
+
+    #[test]
+    fn connection_limits_test() {
+        let config: IPSConfiguration = toml::from_str(
+            r#"
+            peer_file_path = "peers.json"
+            geolocation = "Off"
+            geolocation_minmax_distance_km = 1000
+            change_at_least = 1
+            change_no_more = 2
+            bridge_threshold_adjustment = 1.25
+
+            [mcda_weights]
+            location = 0.0
+            degree = 0.25
+            eigenvector = 0.25
+            betweenness = 0.25
+            closeness = 0.25
+
+            [[connection_limits]]
+            network = "Zcash"
+            max_outbound = 8
+            max_total = 125
+            "#,
+        )
+        .unwrap();
+        assert!(config.validate().is_ok());
+
+        let limits = config.connection_limits();
+        assert_eq!(limits.max_outbound, Some(8));
+        assert_eq!(limits.max_inbound, None);
+        assert_eq!(limits.outbound_room(0), 8);
+        assert_eq!(limits.outbound_room(3), 5);
+        assert_eq!(limits.outbound_room(8), 0);
+        assert_eq!(limits.outbound_room(120), 0);
+        assert!(limits.accepts_inbound(124));
+        assert!(!limits.accepts_inbound(125));
+
+        // Other networks are not limited.
+        let limits = config.for_network(NetworkType::Ripple).connection_limits();
+        assert!(!limits.is_enabled());
+        assert_eq!(limits.outbound_room(1000), usize::MAX);
+
+        let limits = ConnectionLimits {
+            max_outbound: Some(8),
+            max_inbound: Some(2),
+            max_total: None,
+        };
+        assert!(limits.accepts_inbound(9));
+        assert!(!limits.accepts_inbound(10));
+
+        let mut config = config;
+        config
+            .connection_limits
+            .push(config.connection_limits[0].clone());
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn network_file_path_test() {
+        assert_eq!(
+            network_file_path(Path::new("testdata/peers.json"), NetworkType::Zcash),
+            PathBuf::from("testdata/peers-zcash.json")
+        );
+        assert_eq!(
+            network_file_path(Path::new("ips"), NetworkType::Ripple),
+            PathBuf::from("ips-ripple")
+        );
     }
 }
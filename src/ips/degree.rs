@@ -0,0 +1,128 @@
+//! Desired degree.
+//!
+//! Number of peers each node should pursue is given by the configured policy. Network wide
+//! policies (mean, median, percentile and fixed) give the same target to every node, the
+//! others derive it from the current degree of the node.
+
+use std::{collections::HashMap, fmt, net::SocketAddr};
+
+use crate::ips::{
+    config::DesiredDegreePolicy,
+    statistics::{degree_centrality_avg, percentile},
+};
+
+/// Desired degree policy resolved for the degrees of the network
+#[derive(Debug, Clone, PartialEq)]
+pub struct DegreeTarget {
+    policy: DesiredDegreePolicy,
+    resolved: ResolvedPolicy,
+}
+
+/// Everything a policy needs to give the desired degree of a node
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ResolvedPolicy {
+    /// The same target for every node
+    Network(f64),
+    /// Current degree kept within the bounds
+    Clamped { min: u32, max: u32 },
+    /// Current degree moved by the share toward the network mean
+    TowardMean { share: f64, mean: f64 },
+}
+
+impl DegreeTarget {
+    /// Resolve the policy for the given node degrees.
+    pub fn new(policy: DesiredDegreePolicy, degrees: &HashMap<SocketAddr, u32>) -> Self {
+        let values = || degrees.values().copied().collect::<Vec<u32>>();
+
+        let resolved = match policy {
+            DesiredDegreePolicy::Mean => ResolvedPolicy::Network(degree_centrality_avg(degrees)),
+            DesiredDegreePolicy::Median => {
+                ResolvedPolicy::Network(percentile(&values(), 50.0).unwrap_or_default())
+            }
+            DesiredDegreePolicy::Percentile(level) => {
+                ResolvedPolicy::Network(percentile(&values(), level).unwrap_or_default())
+            }
+            DesiredDegreePolicy::Fixed(degree) => ResolvedPolicy::Network(degree as f64),
+            DesiredDegreePolicy::Clamped { min, max } => ResolvedPolicy::Clamped { min, max },
+            DesiredDegreePolicy::TowardMean(share) => ResolvedPolicy::TowardMean {
+                share,
+                mean: degree_centrality_avg(degrees),
+            },
+        };
+
+        DegreeTarget { policy, resolved }
+    }
+
+    /// Number of peers the node with the given degree should pursue.
+    pub fn desired_degree(&self, degree: u32) -> u32 {
+        match self.resolved {
+            ResolvedPolicy::Network(target) => target.round() as u32,
+            ResolvedPolicy::Clamped { min, max } => degree.clamp(min, max),
+            ResolvedPolicy::TowardMean { share, mean } => {
+                (degree as f64 + (mean - degree as f64) * share).round() as u32
+            }
+        }
+    }
+}
+
+impl fmt::Display for DegreeTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.resolved {
+            ResolvedPolicy::Network(target) => {
+                write!(f, "{:?}, target {}", self.policy, target.round())
+            }
+            ResolvedPolicy::Clamped { .. } => write!(f, "{:?}", self.policy),
+            ResolvedPolicy::TowardMean { mean, .. } => {
+                write!(f, "{:?}, network mean {mean:.3}", self.policy)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use super::*;
+
+    #[test]
+    fn desired_degree_test() {
+        let degrees = [1, 2, 2, 3, 12]
+            .iter()
+            .enumerate()
+            .map(|(i, degree)| {
+                (
+                    SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, i as u8)), 1234),
+                    *degree,
+                )
+            })
+            .collect::<HashMap<SocketAddr, u32>>();
+
+        let target = |policy| DegreeTarget::new(policy, &degrees);
+
+        let mean = target(DesiredDegreePolicy::Mean);
+        assert_eq!(mean.desired_degree(1), 4);
+        assert_eq!(mean.to_string(), "Mean, target 4");
+        assert_eq!(target(DesiredDegreePolicy::Median).desired_degree(12), 2);
+        assert_eq!(
+            target(DesiredDegreePolicy::Percentile(75.0)).desired_degree(1),
+            3
+        );
+        assert_eq!(target(DesiredDegreePolicy::Fixed(8)).desired_degree(1), 8);
+
+        let clamped = target(DesiredDegreePolicy::Clamped { min: 2, max: 6 });
+        assert_eq!(clamped.desired_degree(1), 2);
+        assert_eq!(clamped.desired_degree(3), 3);
+        assert_eq!(clamped.desired_degree(12), 6);
+        assert_eq!(clamped.to_string(), "Clamped { min: 2, max: 6 }");
+
+        // Mean is 4, so half of the way from 12 is 8.
+        let toward_mean = target(DesiredDegreePolicy::TowardMean(0.5));
+        assert_eq!(toward_mean.desired_degree(12), 8);
+        assert_eq!(toward_mean.desired_degree(4), 4);
+        assert_eq!(
+            toward_mean.to_string(),
+            "TowardMean(0.5), network mean 4.000"
+        );
+    }
+}
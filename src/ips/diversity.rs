@@ -0,0 +1,102 @@
+//! Peer diversity.
+//!
+//! Eclipse attacks are much easier when all peers of a node come from the same subnet or the
+//! same country. Peers of a node are counted per netgroup, so candidates exceeding the
+//! configured limits can be skipped.
+
+use std::collections::HashMap;
+
+use crate::{ips::config::DiversityConstraint, nodes::subnet, Node};
+
+/// Number of peers of a single node in each subnet and country
+pub struct PeerGroups<'a> {
+    constraint: &'a DiversityConstraint,
+    subnets: HashMap<String, usize>,
+    countries: HashMap<String, usize>,
+}
+
+impl<'a> PeerGroups<'a> {
+    /// Count given peers.
+    pub fn new<'b>(
+        constraint: &'a DiversityConstraint,
+        peers: impl Iterator<Item = &'b Node>,
+    ) -> Self {
+        let mut groups = PeerGroups {
+            constraint,
+            subnets: HashMap::new(),
+            countries: HashMap::new(),
+        };
+        for peer in peers {
+            groups.add(peer);
+        }
+        groups
+    }
+
+    /// Check if the peer can be added without exceeding any limit.
+    pub fn allows(&self, peer: &Node) -> bool {
+        let below = |counts: &HashMap<String, usize>, key: &String, limit: usize| {
+            counts.get(key).copied().unwrap_or_default() < limit
+        };
+
+        if let Some(limit) = self.constraint.max_peers_per_subnet {
+            if !below(&self.subnets, &subnet(peer.addr.ip()), limit) {
+                return false;
+            }
+        }
+
+        if let (Some(limit), Some(country)) = (self.constraint.max_peers_per_country, country(peer))
+        {
+            if !below(&self.countries, country, limit) {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// Count the peer.
+    pub fn add(&mut self, peer: &Node) {
+        *self.subnets.entry(subnet(peer.addr.ip())).or_insert(0) += 1;
+        if let Some(country) = country(peer) {
+            *self.countries.entry(country.clone()).or_insert(0) += 1;
+        }
+    }
+}
+
+fn country(node: &Node) -> Option<&String> {
+    node.geolocation
+        .as_ref()
+        .and_then(|geolocation| geolocation.country.as_ref())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::*;
+
+    #[test]
+    fn peer_groups_test() {
+        let node = |addr: &str| Node {
+            addr: addr.parse::<SocketAddr>().unwrap(),
+            ..Default::default()
+        };
+        let peers = [node("10.1.0.1:8233"), node("10.1.2.3:8233")];
+
+        let constraint = DiversityConstraint {
+            max_peers_per_subnet: Some(2),
+            max_peers_per_country: None,
+        };
+        let mut groups = PeerGroups::new(&constraint, peers[..1].iter());
+        assert!(groups.allows(&peers[1]));
+        groups.add(&peers[1]);
+        assert!(!groups.allows(&node("10.1.200.1:8233")));
+        assert!(groups.allows(&node("10.2.0.1:8233")));
+        assert!(groups.allows(&node("[2001:db8::1]:8233")));
+
+        // Nothing is limited by default.
+        let constraint = DiversityConstraint::default();
+        let groups = PeerGroups::new(&constraint, peers.iter());
+        assert!(groups.allows(&node("10.1.200.1:8233")));
+    }
+}
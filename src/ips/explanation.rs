@@ -0,0 +1,163 @@
+//! Peer list explanations.
+//!
+//! For every node, each peer that was added, kept or removed is listed together with the
+//! contributions of the MCDA factors to its rating (as seen by the node) and a reason code
+//! telling which step of the algorithm made the decision.
+
+use std::{fs, net::SocketAddr, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{CrunchyError, Result};
+
+/// Contributions of the MCDA factors to the peer rating - normalized factor values multiplied
+/// by their weights
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RatingBreakdown {
+    /// Degree contribution
+    pub degree: f64,
+    /// Betweenness contribution
+    pub betweenness: f64,
+    /// Closeness contribution
+    pub closeness: f64,
+    /// Eigenvector contribution
+    pub eigenvector: f64,
+    /// Clustering coefficient contribution
+    pub clustering: f64,
+    /// Location contribution (depends on the node the peer is rated for)
+    pub location: f64,
+}
+
+impl RatingBreakdown {
+    /// Rating of the peer - sum of all contributions.
+    pub fn total(&self) -> f64 {
+        self.degree
+            + self.betweenness
+            + self.closeness
+            + self.eigenvector
+            + self.clustering
+            + self.location
+    }
+}
+
+/// What happened to the peer
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PeerAction {
+    /// Peer is present only in the new peer list
+    Added,
+    /// Peer is present in both peer lists
+    Kept,
+    /// Peer is present only in the original peer list
+    Removed,
+}
+
+/// Why it happened
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PeerReason {
+    /// Kept as the connection is a bridge
+    BridgeProtected,
+    /// Kept as the last connection of the node to another community
+    InterCommunityLink,
+    /// Removed as one of the lowest rated peers of the node with more peers than desired
+    OverDegree,
+    /// Removed as one of the lowest rated peers when peers are changed anyway
+    LowRating,
+    /// Added to the node with less peers than desired
+    UnderDegree,
+    /// Added as one of the best rated candidates (or kept as not being one of the lowest rated)
+    HighRating,
+    /// Added instead of a better candidate which would exceed the diversity limits
+    DiversityPick,
+    /// Added by the peer to its own peer list
+    RequestedByPeer,
+}
+
+impl PeerReason {
+    /// Resolve reason of the final action. Decisions recorded during the peer selection may be
+    /// overridden later (e.g. when the peer list is not rewritten at all), so the recorded
+    /// reason is used only if it matches the action.
+    pub fn resolve(action: PeerAction, recorded: Option<PeerReason>) -> PeerReason {
+        use PeerReason::*;
+
+        match (action, recorded) {
+            (PeerAction::Added, Some(reason @ (UnderDegree | HighRating | DiversityPick))) => {
+                reason
+            }
+            (PeerAction::Added, _) => RequestedByPeer,
+            (PeerAction::Kept, Some(reason @ (BridgeProtected | InterCommunityLink))) => reason,
+            (PeerAction::Kept, _) => HighRating,
+            (PeerAction::Removed, Some(reason @ (OverDegree | LowRating))) => reason,
+            (PeerAction::Removed, _) => LowRating,
+        }
+    }
+}
+
+/// Explanation of a single peer list entry
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PeerExplanation {
+    /// IP address of the peer
+    pub ip: SocketAddr,
+    /// What happened to the peer
+    pub action: PeerAction,
+    /// Why it happened
+    pub reason: PeerReason,
+    /// Rating of the peer as seen by the node
+    pub rating: RatingBreakdown,
+}
+
+/// Explanation of the peer list of a single node
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NodeExplanation {
+    /// IP address of the node
+    pub ip: SocketAddr,
+    /// Number of peers before IPS
+    pub degree: usize,
+    /// Number of peers the node was pursuing
+    pub desired_degree: u32,
+    /// Added, kept and removed peers
+    pub peers: Vec<PeerExplanation>,
+}
+
+/// Write explanations to the file as JSON.
+pub fn save_explanations(explanations: &[NodeExplanation], path: &Path) -> Result<()> {
+    let explanations = serde_json::to_string(explanations)
+        .map_err(|e| CrunchyError::parse("could not serialize explanations", e))?;
+    fs::write(path, explanations).map_err(|e| {
+        CrunchyError::io(
+            format!("could not write explanation file {}", path.display()),
+            e,
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_reason_test() {
+        assert_eq!(
+            PeerReason::resolve(PeerAction::Removed, Some(PeerReason::OverDegree)),
+            PeerReason::OverDegree
+        );
+        assert_eq!(
+            PeerReason::resolve(PeerAction::Kept, Some(PeerReason::BridgeProtected)),
+            PeerReason::BridgeProtected
+        );
+        // Peer chosen for removal but finally kept.
+        assert_eq!(
+            PeerReason::resolve(PeerAction::Kept, Some(PeerReason::LowRating)),
+            PeerReason::HighRating
+        );
+        assert_eq!(
+            PeerReason::resolve(PeerAction::Added, None),
+            PeerReason::RequestedByPeer
+        );
+        assert_eq!(
+            serde_json::to_string(&PeerReason::BridgeProtected).unwrap(),
+            "\"bridge-protected\""
+        );
+    }
+}
@@ -3,6 +3,7 @@ use std::{
     net::SocketAddr,
 };
 
+use serde::{Deserialize, Serialize};
 use spectre::{edge::Edge, graph::Graph};
 use ziggurat_core_crawler::summary::NetworkType;
 
@@ -38,7 +39,7 @@ pub fn find_bridges(nodes: &[Node], threshold_adjustment: f64) -> HashMap<usize,
 
     let mut betweenness_list = nodes.iter().map(|n| n.betweenness).collect::<Vec<f64>>();
 
-    betweenness_list.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    betweenness_list.sort_by(f64::total_cmp);
 
     let betweenness_median = median(&betweenness_list).unwrap(); // Safe to uwrap as we checked if there are at least 2 nodes.
     let betweenness_threshold = betweenness_median * threshold_adjustment;
@@ -73,6 +74,185 @@ pub fn find_bridges(nodes: &[Node], threshold_adjustment: f64) -> HashMap<usize,
     bridges
 }
 
+/// Single points of failure of the graph found with Tarjan's algorithm. All values are indices
+/// of the nodes the analysis was run on.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BridgeAnalysis {
+    /// Connections whose removal disconnects the graph (lower index first)
+    pub bridges: Vec<(usize, usize)>,
+    /// Nodes whose removal disconnects the graph
+    pub articulation_points: Vec<usize>,
+    /// Groups of nodes that stay connected after removal of any single connection
+    pub two_edge_connected_components: Vec<Vec<usize>>,
+}
+
+impl BridgeAnalysis {
+    /// Bridges in the same form as returned by [`find_bridges`].
+    pub fn bridge_map(&self) -> HashMap<usize, HashSet<usize>> {
+        let mut bridges: HashMap<usize, HashSet<usize>> = HashMap::new();
+        for (a, b) in &self.bridges {
+            bridges.entry(*a).or_default().insert(*b);
+            bridges.entry(*b).or_default().insert(*a);
+        }
+        bridges
+    }
+}
+
+/// Find true bridges, articulation points and 2-edge-connected components.
+/// Unlike [`find_bridges`] this is an exact method - Tarjan's algorithm finds them in a single
+/// depth-first search, comparing discovery time of each node with the lowest discovery time
+/// reachable from its subtree using at most one back edge. The search is iterative, as recursion
+/// could overflow the stack for long paths in big networks.
+pub fn find_exact_bridges(nodes: &[Node]) -> BridgeAnalysis {
+    const UNVISITED: usize = usize::MAX;
+
+    let adjacency = adjacency_list(nodes);
+    let mut discovery = vec![UNVISITED; nodes.len()];
+    let mut low = vec![0; nodes.len()];
+    let mut parent = vec![UNVISITED; nodes.len()];
+    let mut articulation = vec![false; nodes.len()];
+    let mut bridges = Vec::new();
+    let mut time = 0;
+
+    for root in 0..nodes.len() {
+        if discovery[root] != UNVISITED {
+            continue;
+        }
+
+        discovery[root] = time;
+        low[root] = time;
+        time += 1;
+        let mut root_children = 0;
+
+        // Stack entries are nodes with position of the next neighbour to visit.
+        let mut stack = vec![(root, 0)];
+        while let Some(&(node, next)) = stack.last() {
+            if let Some(&peer) = adjacency[node].get(next) {
+                stack.last_mut().unwrap().1 += 1;
+
+                if discovery[peer] == UNVISITED {
+                    parent[peer] = node;
+                    discovery[peer] = time;
+                    low[peer] = time;
+                    time += 1;
+                    if node == root {
+                        root_children += 1;
+                    }
+                    stack.push((peer, 0));
+                } else if peer != parent[node] {
+                    low[node] = low[node].min(discovery[peer]);
+                }
+                continue;
+            }
+
+            // All neighbours visited - propagate the result to the parent.
+            stack.pop();
+            if let Some(&(parent_node, _)) = stack.last() {
+                low[parent_node] = low[parent_node].min(low[node]);
+
+                if low[node] > discovery[parent_node] {
+                    bridges.push((parent_node.min(node), parent_node.max(node)));
+                }
+
+                if parent_node != root && low[node] >= discovery[parent_node] {
+                    articulation[parent_node] = true;
+                }
+            }
+        }
+
+        // Root is a special case - it's an articulation point only if it has more than one
+        // subtree in the search tree.
+        if root_children > 1 {
+            articulation[root] = true;
+        }
+    }
+
+    bridges.sort_unstable();
+
+    // Components are what is left connected after cutting all bridges.
+    let bridge_set = bridges.iter().copied().collect::<HashSet<_>>();
+    let mut component_visited = vec![false; nodes.len()];
+    let mut two_edge_connected_components = Vec::new();
+    for start in 0..nodes.len() {
+        if component_visited[start] {
+            continue;
+        }
+
+        component_visited[start] = true;
+        let mut component = vec![start];
+        let mut stack = vec![start];
+        while let Some(node) = stack.pop() {
+            for &peer in &adjacency[node] {
+                if component_visited[peer] || bridge_set.contains(&(node.min(peer), node.max(peer)))
+                {
+                    continue;
+                }
+                component_visited[peer] = true;
+                component.push(peer);
+                stack.push(peer);
+            }
+        }
+        component.sort_unstable();
+        two_edge_connected_components.push(component);
+    }
+
+    BridgeAnalysis {
+        bridges,
+        articulation_points: (0..nodes.len()).filter(|idx| articulation[*idx]).collect(),
+        two_edge_connected_components,
+    }
+}
+
+/// Build undirected adjacency list from the nodes connections. Connections are made symmetric,
+/// duplicates, self connections and connections to non-existing nodes are dropped.
+pub fn adjacency_list(nodes: &[Node]) -> Vec<Vec<usize>> {
+    build_adjacency(nodes.iter().map(|node| &node.connections))
+}
+
+/// Build undirected adjacency list from the raw connection indices (eg. taken from the crawler
+/// sample) - the same way as [`adjacency_list`] does for nodes.
+pub fn adjacency_from_indices(indices: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    build_adjacency(indices.iter())
+}
+
+fn build_adjacency<'a>(
+    connections: impl ExactSizeIterator<Item = &'a Vec<usize>>,
+) -> Vec<Vec<usize>> {
+    let nodes_count = connections.len();
+    let mut adjacency = vec![Vec::new(); nodes_count];
+
+    for (node_idx, peers) in connections.enumerate() {
+        for peer_idx in peers {
+            if *peer_idx >= nodes_count || *peer_idx == node_idx {
+                continue;
+            }
+            adjacency[node_idx].push(*peer_idx);
+            adjacency[*peer_idx].push(node_idx);
+        }
+    }
+
+    for peers in adjacency.iter_mut() {
+        peers.sort_unstable();
+        peers.dedup();
+    }
+
+    adjacency
+}
+
+/// Count connections between nodes of different communities (each connection counted once).
+pub fn inter_community_connections(nodes: &[Node]) -> usize {
+    adjacency_list(nodes)
+        .iter()
+        .enumerate()
+        .map(|(node, peers)| {
+            peers
+                .iter()
+                .filter(|&&peer| peer > node && nodes[peer].community != nodes[node].community)
+                .count()
+        })
+        .sum()
+}
+
 /// Reconstruct graph from nodes and their connection subfield. This step is used to run
 /// some graph algorithms on the graph (like betweenness centrality).
 pub fn construct_graph(nodes: &[Node]) -> Graph<SocketAddr> {
@@ -136,20 +316,14 @@ pub fn remove_node(nodes: &mut Vec<Node>, node_idx: usize) {
     }
 }
 
-/// Find node with lowest betweenness centrality in the provided nodes indexes.
-pub fn find_lowest_betweenness(nodes_idx: &[usize], state: &IpsState) -> usize {
-    let mut lowest_betweenness = f64::MAX;
-    let mut lowest_betweenness_idx = 0;
-
-    for idx in nodes_idx.iter() {
-        let betweenness = state.nodes[*idx].betweenness;
-        if betweenness < lowest_betweenness {
-            lowest_betweenness = betweenness;
-            lowest_betweenness_idx = *idx;
-        }
-    }
-
-    lowest_betweenness_idx
+/// Find node with lowest betweenness centrality in the provided nodes indexes (none if there are
+/// no indexes).
+pub fn find_lowest_betweenness(nodes_idx: &[usize], state: &IpsState) -> Option<usize> {
+    nodes_idx.iter().copied().min_by(|a, b| {
+        state.nodes[*a]
+            .betweenness
+            .total_cmp(&state.nodes[*b].betweenness)
+    })
 }
 
 /// Create new vector with nodes that have common network type.
@@ -289,6 +463,63 @@ mod tests {
         assert!(peers.contains(&4));
     }
 
+    #[test]
+    fn find_exact_bridges_test() {
+        // Two triangles (0, 1, 2) and (3, 4, 5) joined by the 2-3 connection, with node 6
+        // hanging on node 5 and isolated node 7.
+        let connections = vec![
+            vec![1, 2],
+            vec![0, 2],
+            vec![0, 1, 3],
+            vec![2, 4, 5],
+            vec![3, 5],
+            vec![3, 4, 6],
+            vec![5],
+            vec![],
+        ];
+        let nodes = connections
+            .into_iter()
+            .enumerate()
+            .map(|(i, connections)| Node {
+                addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(i as u8, 0, 0, 0)), 1234),
+                connections,
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+
+        let analysis = find_exact_bridges(&nodes);
+        assert_eq!(analysis.bridges, vec![(2, 3), (5, 6)]);
+        assert_eq!(analysis.articulation_points, vec![2, 3, 5]);
+        assert_eq!(
+            analysis.two_edge_connected_components,
+            vec![vec![0, 1, 2], vec![3, 4, 5], vec![6], vec![7]]
+        );
+
+        let bridges = analysis.bridge_map();
+        assert!(bridges[&2].contains(&3));
+        assert!(bridges[&3].contains(&2));
+        assert!(!bridges.contains_key(&0));
+    }
+
+    #[test]
+    fn inter_community_connections_test() {
+        // Triangle (0, 1, 2) in community 0 linked with connection (2, 3) to pair (3, 4) in
+        // community 1. Connection 3 - 2 is listed on one side only.
+        let connections = vec![vec![1, 2], vec![0, 2], vec![0, 1, 3], vec![4], vec![3]];
+        let nodes = connections
+            .into_iter()
+            .enumerate()
+            .map(|(i, connections)| Node {
+                addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(i as u8, 0, 0, 0)), 1234),
+                community: usize::from(i > 2),
+                connections,
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(inter_community_connections(&nodes), 1);
+    }
+
     #[test]
     fn filter_network_test() {
         let nodes = vec![
@@ -1,6 +1,11 @@
 pub mod algorithm;
 pub mod config;
-mod graph_utils;
+mod degree;
+pub mod diversity;
+pub mod explanation;
+pub mod graph_utils;
 mod normalization;
-mod peer;
+pub mod partition;
+pub mod peer;
+pub mod simulation;
 mod statistics;
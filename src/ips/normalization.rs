@@ -18,19 +18,11 @@ impl NormalizationFactors {
             return None;
         }
 
-        let min = list
-            .iter()
-            .min_by(|a, b| a.partial_cmp(b).unwrap())
-            .unwrap();
-        let max = list
-            .iter()
-            .max_by(|a, b| a.partial_cmp(b).unwrap())
-            .unwrap();
+        let values = list.iter().map(|value| (*value).into());
+        let min = values.clone().fold(f64::INFINITY, f64::min);
+        let max = values.fold(f64::NEG_INFINITY, f64::max);
 
-        Some(NormalizationFactors {
-            min: (*min).into(),
-            max: (*max).into(),
-        })
+        Some(NormalizationFactors { min, max })
     }
 
     /// Scale value to [0.0, 1.0] range.
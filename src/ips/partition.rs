@@ -0,0 +1,144 @@
+//! Partition report.
+//!
+//! When the network is split into more than one massive island, IPS doesn't try to merge them -
+//! islands may already follow their own chain history, so merging is left to the operators.
+//! The report gives them the data to decide: island membership, sizes, geolocation spread and
+//! the best rated pairs of peers that could bridge the islands.
+
+use std::{collections::HashMap, fs, net::SocketAddr, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{CrunchyError, Result},
+    Node,
+};
+
+/// Single island (connected component) of the partitioned network
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IslandReport {
+    /// Identifier of the island - islands are ordered by size, so 0 is the biggest one
+    pub id: usize,
+    /// Number of nodes in the island
+    pub size: usize,
+    /// Share of all network nodes which belong to the island
+    pub share: f64,
+    /// Whether the island exceeds the massive island threshold
+    pub massive: bool,
+    /// Addresses of the island nodes
+    pub nodes: Vec<SocketAddr>,
+    /// Number of nodes with known coordinates
+    pub geolocated: usize,
+    /// Number of nodes in each country, most common first
+    pub countries: Vec<(String, usize)>,
+    /// The greatest distance in km between two geolocated nodes of the island
+    pub spread_km: f64,
+}
+
+/// Pair of peers which would connect two massive islands
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BridgeSuggestion {
+    /// Identifiers of the connected islands
+    pub islands: (usize, usize),
+    /// Peers to be connected, first one from the first island
+    pub peers: (SocketAddr, SocketAddr),
+    /// MCDA score of the pair - sum of both peers ratings and their location rating
+    pub score: f64,
+}
+
+/// Islands of the partitioned network and suggested bridges between the massive ones
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PartitionReport {
+    /// All islands, biggest first
+    pub islands: Vec<IslandReport>,
+    /// Suggested bridges, best scored first for each pair of islands
+    pub bridge_suggestions: Vec<BridgeSuggestion>,
+}
+
+impl IslandReport {
+    /// Describe island consisting of nodes with given indices.
+    pub fn new(id: usize, island: &[usize], nodes: &[Node], massive: bool) -> Self {
+        let mut indices = island.to_vec();
+        indices.sort_unstable();
+
+        let mut countries = HashMap::new();
+        let mut coordinates = Vec::new();
+        for idx in &indices {
+            if let Some(geolocation) = &nodes[*idx].geolocation {
+                if let Some(country) = &geolocation.country {
+                    *countries.entry(country.clone()).or_insert(0) += 1;
+                }
+                if let Some(location) = geolocation.coordinates {
+                    coordinates.push(location);
+                }
+            }
+        }
+
+        let mut countries = countries.into_iter().collect::<Vec<(String, usize)>>();
+        countries.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+        let mut spread_m = 0.0f64;
+        for (i, a) in coordinates.iter().enumerate() {
+            for b in &coordinates[i + 1..] {
+                spread_m = spread_m.max(a.distance_to(*b));
+            }
+        }
+
+        IslandReport {
+            id,
+            size: indices.len(),
+            share: indices.len() as f64 / nodes.len() as f64,
+            massive,
+            nodes: indices.iter().map(|idx| nodes[*idx].addr).collect(),
+            geolocated: coordinates.len(),
+            countries,
+            spread_km: spread_m / 1000.0,
+        }
+    }
+}
+
+impl PartitionReport {
+    /// Write report to the file as JSON.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let report = serde_json::to_string(self)
+            .map_err(|e| CrunchyError::parse("could not serialize partition report", e))?;
+        fs::write(path, report).map_err(|e| {
+            CrunchyError::io(
+                format!("could not write partition report {}", path.display()),
+                e,
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use super::*;
+
+    #[test]
+    fn island_report_test() {
+        let nodes = (0..5)
+            .map(|i| Node {
+                addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, i)), 1234),
+                ..Default::default()
+            })
+            .collect::<Vec<Node>>();
+
+        let report = IslandReport::new(1, &[3, 0, 1, 2], &nodes, true);
+        assert_eq!(report.id, 1);
+        assert_eq!(report.size, 4);
+        assert!((report.share - 0.8).abs() < 1e-12);
+        assert!(report.massive);
+        assert_eq!(
+            report.nodes,
+            vec![nodes[0].addr, nodes[1].addr, nodes[2].addr, nodes[3].addr]
+        );
+
+        // Nodes without geolocation don't count to the spread.
+        assert_eq!(report.geolocated, 0);
+        assert!(report.countries.is_empty());
+        assert_eq!(report.spread_km, 0.0);
+    }
+}
@@ -1,8 +1,11 @@
-use std::net::SocketAddr;
+use std::{collections::HashMap, fs, net::SocketAddr, path::Path};
 
 use serde::{Deserialize, Serialize};
 
-use crate::Node;
+use crate::{
+    error::{CrunchyError, Result},
+    Node,
+};
 
 /// Peer list structure containing peer list for each node
 #[derive(Clone, Serialize, Deserialize)]
@@ -44,6 +47,139 @@ impl Peer {
     }
 }
 
+/// Difference between two peer lists of the same node
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PeerDiff {
+    /// IP address of the node
+    pub ip: SocketAddr,
+    /// Peers present only in the new list
+    pub add: Vec<SocketAddr>,
+    /// Peers present only in the old list
+    pub remove: Vec<SocketAddr>,
+}
+
+impl PeerDiff {
+    /// Compare two sets of peer lists. Nodes present only in one of the sets are compared
+    /// against an empty list. Nodes without any change are skipped.
+    pub fn compare(old: &[Peer], new: &[Peer]) -> Vec<PeerDiff> {
+        let old_lists = old
+            .iter()
+            .map(|peer| (peer.ip, &peer.list))
+            .collect::<HashMap<SocketAddr, &Vec<SocketAddr>>>();
+        let new_lists = new
+            .iter()
+            .map(|peer| (peer.ip, &peer.list))
+            .collect::<HashMap<SocketAddr, &Vec<SocketAddr>>>();
+
+        // Keep order of the new set, then append nodes that disappeared.
+        let mut ips = new.iter().map(|peer| peer.ip).collect::<Vec<_>>();
+        ips.extend(
+            old.iter()
+                .map(|peer| peer.ip)
+                .filter(|ip| !new_lists.contains_key(ip)),
+        );
+
+        let empty = Vec::new();
+        let mut diffs = Vec::new();
+        for ip in ips {
+            let old_list = old_lists.get(&ip).copied().unwrap_or(&empty);
+            let new_list = new_lists.get(&ip).copied().unwrap_or(&empty);
+
+            let diff = PeerDiff {
+                ip,
+                add: new_list
+                    .iter()
+                    .filter(|peer| !old_list.contains(peer))
+                    .copied()
+                    .collect(),
+                remove: old_list
+                    .iter()
+                    .filter(|peer| !new_list.contains(peer))
+                    .copied()
+                    .collect(),
+            };
+
+            if !diff.add.is_empty() || !diff.remove.is_empty() {
+                diffs.push(diff);
+            }
+        }
+
+        diffs
+    }
+}
+
+/// Aggregate numbers of the peer list changes
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PeerChurn {
+    /// Number of nodes with any change
+    pub nodes_changed: usize,
+    /// Share of the nodes with any change
+    pub nodes_changed_share: f64,
+    /// Number of added peer list entries
+    pub added: usize,
+    /// Number of removed peer list entries
+    pub removed: usize,
+    /// Number of changed entries (added and removed) relative to number of the old entries
+    pub churn_rate: f64,
+}
+
+impl PeerChurn {
+    /// Sum up the differences between the old and new peer lists.
+    pub fn new(old: &[Peer], diffs: &[PeerDiff]) -> PeerChurn {
+        let added = diffs.iter().map(|diff| diff.add.len()).sum::<usize>();
+        let removed = diffs.iter().map(|diff| diff.remove.len()).sum::<usize>();
+        let old_entries = old.iter().map(|peer| peer.list.len()).sum::<usize>();
+
+        PeerChurn {
+            nodes_changed: diffs.len(),
+            nodes_changed_share: if old.is_empty() {
+                0.0
+            } else {
+                diffs.len() as f64 / old.len() as f64
+            },
+            added,
+            removed,
+            churn_rate: if old_entries == 0 {
+                0.0
+            } else {
+                (added + removed) as f64 / old_entries as f64
+            },
+        }
+    }
+}
+
+/// Changes to be applied to the current peer lists
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PeerDelta {
+    /// Aggregate numbers of the changes
+    pub churn: PeerChurn,
+    /// Peers to be added and removed for every changed node
+    pub nodes: Vec<PeerDiff>,
+}
+
+impl PeerDelta {
+    /// Compare current peer lists with the new ones.
+    pub fn new(old: &[Peer], new: &[Peer]) -> PeerDelta {
+        let nodes = PeerDiff::compare(old, new);
+        PeerDelta {
+            churn: PeerChurn::new(old, &nodes),
+            nodes,
+        }
+    }
+
+    /// Write delta to the file as JSON.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let delta = serde_json::to_string(self)
+            .map_err(|e| CrunchyError::parse("could not serialize peer delta", e))?;
+        fs::write(path, delta).map_err(|e| {
+            CrunchyError::io(
+                format!("could not write peer delta file {}", path.display()),
+                e,
+            )
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::net::{IpAddr, Ipv4Addr, SocketAddr};
@@ -70,9 +206,100 @@ mod tests {
             },
         ];
 
-        let peer = Peer::generate_peerlist(nodes.get(0).unwrap(), &nodes);
+        let peer = Peer::generate_peerlist(nodes.first().unwrap(), &nodes);
         assert_eq!(peer.list.len(), 2);
         assert!(peer.list.contains(&nodes.get(1).unwrap().addr));
         assert!(peer.list.contains(&nodes.get(2).unwrap().addr));
     }
+
+    #[test]
+    fn peer_diff_compare_test() {
+        let a = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(1, 0, 0, 0)), 1234);
+        let b = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(2, 0, 0, 0)), 1234);
+        let c = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(3, 0, 0, 0)), 1234);
+
+        let old = vec![
+            Peer {
+                ip: a,
+                list: vec![b],
+            },
+            Peer {
+                ip: b,
+                list: vec![a],
+            },
+        ];
+        let new = vec![
+            Peer {
+                ip: a,
+                list: vec![c],
+            },
+            Peer {
+                ip: b,
+                list: vec![a],
+            },
+            Peer {
+                ip: c,
+                list: vec![a],
+            },
+        ];
+
+        let diffs = PeerDiff::compare(&old, &new);
+        assert_eq!(diffs.len(), 2);
+        assert_eq!(diffs[0].ip, a);
+        assert_eq!(diffs[0].add, vec![c]);
+        assert_eq!(diffs[0].remove, vec![b]);
+        assert_eq!(diffs[1].ip, c);
+        assert_eq!(diffs[1].add, vec![a]);
+        assert!(diffs[1].remove.is_empty());
+    }
+
+    #[test]
+    fn peer_delta_test() {
+        let a = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(1, 0, 0, 0)), 1234);
+        let b = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(2, 0, 0, 0)), 1234);
+        let c = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(3, 0, 0, 0)), 1234);
+
+        let old = vec![
+            Peer {
+                ip: a,
+                list: vec![b, c],
+            },
+            Peer {
+                ip: b,
+                list: vec![a],
+            },
+            Peer {
+                ip: c,
+                list: vec![a],
+            },
+        ];
+        let new = vec![
+            Peer {
+                ip: a,
+                list: vec![b],
+            },
+            Peer {
+                ip: b,
+                list: vec![a, c],
+            },
+            Peer {
+                ip: c,
+                list: vec![a],
+            },
+        ];
+
+        let delta = PeerDelta::new(&old, &new);
+        assert_eq!(delta.nodes.len(), 2);
+        assert_eq!(
+            delta.churn,
+            PeerChurn {
+                nodes_changed: 2,
+                nodes_changed_share: 2.0 / 3.0,
+                added: 1,
+                removed: 1,
+                churn_rate: 0.5,
+            }
+        );
+        assert_eq!(PeerChurn::new(&[], &[]), PeerChurn::default());
+    }
 }
@@ -0,0 +1,232 @@
+//! Multi-round IPS simulation.
+//!
+//! IPS changes only a few peers of every node at once, so the network is expected to evolve
+//! over many runs. The simulation runs IPS repeatedly, each round over the network produced by
+//! the previous one, and only randomly chosen nodes accept their new peer lists. Statistics of
+//! every round tell if the strategy converges or oscillates.
+
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fs,
+    path::Path,
+};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use ziggurat_core_crawler::summary::NetworkType;
+
+use crate::{
+    config::AnalysisConfiguration,
+    error::{CrunchyError, Result},
+    ips::{
+        algorithm::Ips,
+        config::IPSConfiguration,
+        graph_utils::filter_network,
+        peer::{Peer, PeerChurn, PeerDiff},
+        statistics::Statistics,
+    },
+    nodes::graph_hash,
+    CrunchyState, Node,
+};
+
+/// Network after a single simulation round
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SimulationRound {
+    /// Round number, 0 is the crawled network
+    pub round: usize,
+    /// Number of nodes which accepted their new peer lists
+    pub adopted: usize,
+    /// Peer list changes relative to the previous round
+    pub churn: PeerChurn,
+    /// Statistics of the network after the round
+    pub statistics: Statistics,
+}
+
+/// Run IPS for the configured number of rounds over the nodes of the given network. Every node
+/// accepts its new peer list with the configured adoption probability.
+pub async fn simulate_rounds(
+    state: &CrunchyState,
+    config: &IPSConfiguration,
+    analysis: &AnalysisConfiguration,
+    network: NetworkType,
+    num_threads: usize,
+) -> Result<Vec<SimulationRound>> {
+    let simulation = &config.simulation;
+
+    // Only the peer lists are needed from every round.
+    let mut round_config = config.clone();
+    round_config.vanilla_peer_file_path = None;
+    round_config.partition_report_path = None;
+    round_config.explanation_file_path = None;
+    round_config.delta_file_path = None;
+    // A rejected round would leave the network unchanged, so all the next rounds would
+    // generate the same peer lists again.
+    round_config.reject_lower_connectivity = false;
+    let mut ips = Ips::new(round_config, analysis.clone());
+
+    let nodes = filter_network(&state.nodes, network);
+    if nodes.is_empty() {
+        return Err(CrunchyError::Topology(format!(
+            "there are no {network:?} nodes in the state"
+        )));
+    }
+
+    let (mut nodes, statistics) = ips.recompute_network(&nodes, num_threads)?;
+    let mut rounds = vec![SimulationRound {
+        round: 0,
+        adopted: 0,
+        churn: PeerChurn::default(),
+        statistics,
+    }];
+
+    let mut rng = StdRng::seed_from_u64(simulation.seed);
+    for round in 1..=simulation.rounds {
+        // Centralities were just recomputed, the matching hash lets IPS reuse them.
+        let round_state = CrunchyState {
+            graph_hash: graph_hash(&nodes),
+            nodes: nodes.clone(),
+            ..Default::default()
+        };
+        let peers = ips.generate(&round_state, network, num_threads).await?;
+
+        let adopted = (0..nodes.len())
+            .map(|_| rng.gen_bool(simulation.adoption_probability))
+            .collect::<Vec<bool>>();
+        let next_nodes = adopt_peer_lists(&nodes, &peers, &adopted);
+
+        let old = Peer::generate_all_peerlists(&nodes);
+        let new = Peer::generate_all_peerlists(&next_nodes);
+        let churn = PeerChurn::new(&old, &PeerDiff::compare(&old, &new));
+
+        let (next_nodes, statistics) = ips.recompute_network(&next_nodes, num_threads)?;
+        nodes = next_nodes;
+        rounds.push(SimulationRound {
+            round,
+            adopted: adopted.iter().filter(|adopted| **adopted).count(),
+            churn,
+            statistics,
+        });
+    }
+
+    Ok(rounds)
+}
+
+/// Apply new peer lists of the adopting nodes. A connection survives only if every adopting
+/// end keeps it in its new peer list - connections between the other nodes stay as they were.
+pub fn adopt_peer_lists(nodes: &[Node], peers: &[Peer], adopted: &[bool]) -> Vec<Node> {
+    let indices = nodes
+        .iter()
+        .enumerate()
+        .map(|(idx, node)| (node.addr, idx))
+        .collect::<HashMap<_, _>>();
+
+    let mut wanted = vec![HashSet::new(); nodes.len()];
+    for peer in peers {
+        if let Some(idx) = indices.get(&peer.ip) {
+            wanted[*idx] = peer
+                .list
+                .iter()
+                .filter_map(|addr| indices.get(addr).copied())
+                .collect();
+        }
+    }
+
+    let mut pairs = BTreeSet::new();
+    for (idx, node) in nodes.iter().enumerate() {
+        let new_peers = wanted[idx].iter().filter(|_| adopted[idx]);
+        for peer in node.connections.iter().chain(new_peers) {
+            if *peer != idx {
+                pairs.insert((idx.min(*peer), idx.max(*peer)));
+            }
+        }
+    }
+
+    let mut next_nodes = nodes.to_vec();
+    for node in &mut next_nodes {
+        node.connections.clear();
+    }
+
+    for (a, b) in pairs {
+        let connected = match (adopted[a], adopted[b]) {
+            (false, false) => {
+                nodes[a].connections.contains(&b) || nodes[b].connections.contains(&a)
+            }
+            (true, false) => wanted[a].contains(&b),
+            (false, true) => wanted[b].contains(&a),
+            (true, true) => wanted[a].contains(&b) && wanted[b].contains(&a),
+        };
+        if connected {
+            next_nodes[a].connections.push(b);
+            next_nodes[b].connections.push(a);
+        }
+    }
+
+    next_nodes
+}
+
+/// Write simulation rounds to the file as JSON.
+pub fn save_rounds(rounds: &[SimulationRound], path: &Path) -> Result<()> {
+    let rounds = serde_json::to_string(rounds)
+        .map_err(|e| CrunchyError::parse("could not serialize simulation rounds", e))?;
+    fs::write(path, rounds).map_err(|e| {
+        CrunchyError::io(
+            format!("could not write simulation file {}", path.display()),
+            e,
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+    use super::*;
+
+    #[test]
+    fn adopt_peer_lists_test() {
+        let addr = |i: u8| SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, i)), 1234);
+        // Path 0 - 1 - 2 - 3
+        let nodes = [vec![1], vec![0, 2], vec![1, 3], vec![2]]
+            .into_iter()
+            .enumerate()
+            .map(|(i, connections)| Node {
+                addr: addr(i as u8),
+                connections,
+                ..Default::default()
+            })
+            .collect::<Vec<Node>>();
+
+        // IPS proposes the cycle 0 - 1 - 3 - 2 - 0.
+        let peers = [vec![1, 2], vec![0, 3], vec![0, 3], vec![1, 2]]
+            .into_iter()
+            .enumerate()
+            .map(|(i, list)| Peer {
+                ip: addr(i as u8),
+                list: list.into_iter().map(addr).collect(),
+            })
+            .collect::<Vec<Peer>>();
+
+        // Everybody adopts.
+        let next = adopt_peer_lists(&nodes, &peers, &[true; 4]);
+        let connections = next
+            .iter()
+            .map(|node| node.connections.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            connections,
+            vec![vec![1, 2], vec![0, 3], vec![0, 3], vec![1, 2]]
+        );
+
+        // Node 1 adopts only: it drops node 2 and connects to node 3, the rest stays.
+        let next = adopt_peer_lists(&nodes, &peers, &[false, true, false, false]);
+        let connections = next
+            .iter()
+            .map(|node| node.connections.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(connections, vec![vec![1], vec![0, 3], vec![3], vec![1, 2]]);
+
+        // Nobody adopts.
+        let next = adopt_peer_lists(&nodes, &peers, &[false; 4]);
+        assert_eq!(next[1].connections, nodes[1].connections);
+    }
+}
@@ -1,10 +1,18 @@
-use std::{collections::HashMap, io::Write, net::SocketAddr};
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+    net::SocketAddr,
+};
+
+use serde::{Deserialize, Serialize};
 
 use crate::ips::algorithm::IpsState;
 
 /// This struct is used to store statistics for network at some point in time.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Statistics {
     nodes_count: usize,
+    algebraic_connectivity: Option<f64>,
     degree_average: f64,
     degree_median: f64,
     degree_min: f64,
@@ -21,16 +29,33 @@ pub struct Statistics {
     eigenvector_median: f64,
     eigenvector_min: f64,
     eigenvector_max: f64,
+    clustering_average: f64,
+    clustering_median: f64,
+    clustering_min: f64,
+    clustering_max: f64,
+    transitivity: f64,
+    eccentricity_average: f64,
+    diameter: usize,
+    radius: usize,
+    average_path_length: f64,
+    hop_distribution: Vec<usize>,
+    communities_count: usize,
+    modularity: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    eclipse_risk_average: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    high_eclipse_risk_nodes: Option<usize>,
 }
 
 /// Calculates statistics for given network state.
 pub fn generate_statistics(state: &IpsState) -> Statistics {
     Statistics {
         nodes_count: state.nodes.len(),
+        algebraic_connectivity: state.algebraic_connectivity,
 
         degree_average: degree_centrality_avg(&state.degrees),
         degree_median: median::<u32>(&state.degrees.values().copied().collect::<Vec<u32>>())
-            .expect("can't calculate median"),
+            .unwrap_or_default(),
         degree_min: state.degree_factors.min,
         degree_max: state.degree_factors.max,
 
@@ -48,7 +73,7 @@ pub fn generate_statistics(state: &IpsState) -> Statistics {
                 .map(|n| n.betweenness)
                 .collect::<Vec<f64>>(),
         )
-        .expect("can't calculate median"),
+        .unwrap_or_default(),
         betweenness_min: state.betweenness_factors.min,
         betweenness_max: state.betweenness_factors.max,
 
@@ -66,7 +91,7 @@ pub fn generate_statistics(state: &IpsState) -> Statistics {
                 .map(|n| n.closeness)
                 .collect::<Vec<f64>>(),
         )
-        .expect("can't calculate median"),
+        .unwrap_or_default(),
         closeness_min: state.closeness_factors.min,
         closeness_max: state.closeness_factors.max,
 
@@ -76,65 +101,134 @@ pub fn generate_statistics(state: &IpsState) -> Statistics {
         eigenvector_median: median::<f64>(
             &state.eigenvalues.values().copied().collect::<Vec<f64>>(),
         )
-        .expect("can't calculate median"),
+        .unwrap_or_default(),
         eigenvector_min: state.eigenvector_factors.min,
         eigenvector_max: state.eigenvector_factors.max,
+
+        clustering_average: state.clustering.average_clustering,
+        clustering_median: median::<f64>(
+            &state
+                .nodes
+                .iter()
+                .map(|n| n.clustering)
+                .collect::<Vec<f64>>(),
+        )
+        .unwrap_or_default(),
+        clustering_min: state.clustering_factors.min,
+        clustering_max: state.clustering_factors.max,
+        transitivity: state.clustering.transitivity,
+
+        eccentricity_average: centrality_avg(
+            &state
+                .nodes
+                .iter()
+                .map(|n| n.eccentricity as f64)
+                .collect::<Vec<f64>>(),
+        ),
+        diameter: state.distances.diameter,
+        radius: state.distances.radius,
+        average_path_length: state.distances.average_path_length,
+        hop_distribution: state.distances.hop_distribution.clone(),
+
+        communities_count: state.communities.sizes.len(),
+        modularity: state.communities.modularity,
+
+        eclipse_risk_average: state.eclipse.as_ref().map(|eclipse| eclipse.average_risk),
+        high_eclipse_risk_nodes: state
+            .eclipse
+            .as_ref()
+            .map(|eclipse| eclipse.high_risk_nodes),
     }
 }
 
 /// Prints statistics to given output.
-pub fn print_statistics(o: &mut Box<dyn Write>, stats: &Statistics) {
-    writeln!(o, "----------------------------------------").unwrap();
-    writeln!(o, "Nodes count: {}", stats.nodes_count).unwrap();
-    writeln!(o, "\nDegree measures:").unwrap();
-    writeln!(o, "Average: {}", stats.degree_average).unwrap();
-    writeln!(o, "Median: {}", stats.degree_median).unwrap();
+pub fn print_statistics(o: &mut Box<dyn Write>, stats: &Statistics) -> io::Result<()> {
+    writeln!(o, "----------------------------------------")?;
+    writeln!(o, "Nodes count: {}", stats.nodes_count)?;
+    match stats.algebraic_connectivity {
+        Some(connectivity) => writeln!(o, "Algebraic connectivity: {connectivity}")?,
+        None => writeln!(o, "Algebraic connectivity: not converged")?,
+    }
+    writeln!(o, "\nDegree measures:")?;
+    writeln!(o, "Average: {}", stats.degree_average)?;
+    writeln!(o, "Median: {}", stats.degree_median)?;
     writeln!(
         o,
         "Min: {}, max: {}, delta: {}",
         stats.degree_min,
         stats.degree_max,
         stats.degree_max - stats.degree_min
-    )
-    .unwrap();
+    )?;
 
-    writeln!(o, "\nBetweenness measures:").unwrap();
-    writeln!(o, "Average: {}", stats.betweenness_average).unwrap();
-    writeln!(o, "Median: {}", stats.betweenness_median).unwrap();
+    writeln!(o, "\nBetweenness measures:")?;
+    writeln!(o, "Average: {}", stats.betweenness_average)?;
+    writeln!(o, "Median: {}", stats.betweenness_median)?;
     writeln!(
         o,
         "Min: {}, max: {}, delta: {}",
         stats.betweenness_min,
         stats.betweenness_max,
         stats.betweenness_max - stats.betweenness_min
-    )
-    .unwrap();
+    )?;
 
-    writeln!(o, "\nCloseness measures:").unwrap();
-    writeln!(o, "Average: {}", stats.closeness_average).unwrap();
-    writeln!(o, "Median: {}", stats.closeness_median).unwrap();
+    writeln!(o, "\nCloseness measures:")?;
+    writeln!(o, "Average: {}", stats.closeness_average)?;
+    writeln!(o, "Median: {}", stats.closeness_median)?;
     writeln!(
         o,
         "Min: {}, max: {}, delta: {}",
         stats.closeness_min,
         stats.closeness_max,
         stats.closeness_max - stats.closeness_min
-    )
-    .unwrap();
+    )?;
 
-    writeln!(o, "\nEigenvector measures:").unwrap();
-    writeln!(o, "Average: {}", stats.eigenvector_average).unwrap();
-    writeln!(o, "Median: {}", stats.eigenvector_median).unwrap();
+    writeln!(o, "\nEigenvector measures:")?;
+    writeln!(o, "Average: {}", stats.eigenvector_average)?;
+    writeln!(o, "Median: {}", stats.eigenvector_median)?;
     writeln!(
         o,
         "Min: {}, max: {}, delta: {}",
         stats.eigenvector_min,
         stats.eigenvector_max,
         stats.eigenvector_max - stats.eigenvector_min
-    )
-    .unwrap();
+    )?;
 
-    writeln!(o, "----------------------------------------\n").unwrap();
+    writeln!(o, "\nClustering measures:")?;
+    writeln!(o, "Average: {}", stats.clustering_average)?;
+    writeln!(o, "Median: {}", stats.clustering_median)?;
+    writeln!(
+        o,
+        "Min: {}, max: {}, delta: {}",
+        stats.clustering_min,
+        stats.clustering_max,
+        stats.clustering_max - stats.clustering_min
+    )?;
+    writeln!(o, "Transitivity: {}", stats.transitivity)?;
+
+    writeln!(o, "\nDistance measures:")?;
+    writeln!(o, "Average eccentricity: {}", stats.eccentricity_average)?;
+    writeln!(o, "Diameter: {}, radius: {}", stats.diameter, stats.radius)?;
+    writeln!(o, "Average path length: {}", stats.average_path_length)?;
+    writeln!(o, "Hop distribution:")?;
+    for (hops, count) in stats.hop_distribution.iter().enumerate().skip(1) {
+        writeln!(o, "{hops} hops: {count}")?;
+    }
+
+    writeln!(o, "\nCommunity measures:")?;
+    writeln!(
+        o,
+        "Communities: {}, modularity: {}",
+        stats.communities_count, stats.modularity
+    )?;
+
+    if let (Some(average), Some(high_risk_nodes)) =
+        (stats.eclipse_risk_average, stats.high_eclipse_risk_nodes)
+    {
+        writeln!(o, "\nEclipse risk measures:")?;
+        writeln!(o, "Average: {average}, high risk nodes: {high_risk_nodes}")?;
+    }
+
+    writeln!(o, "----------------------------------------\n")
 }
 
 /// Calculates percentage change between two values.
@@ -156,31 +250,40 @@ pub fn print_statistics_delta(
     o: &mut Box<dyn Write>,
     stats: &Statistics,
     stats_original: &Statistics,
-) {
-    writeln!(o, "Deltas for given statistics pair:").unwrap();
-    writeln!(o, "----------------------------------------").unwrap();
+) -> io::Result<()> {
+    writeln!(o, "Deltas for given statistics pair:")?;
+    writeln!(o, "----------------------------------------")?;
     writeln!(
         o,
         "Nodes count: {} ({:.3}%)",
         stats.nodes_count - stats_original.nodes_count,
         percentage_change(stats_original.nodes_count as f64, stats.nodes_count as f64)
-    )
-    .unwrap();
-    writeln!(o, "\nDegree measures:").unwrap();
+    )?;
+    match (
+        stats.algebraic_connectivity,
+        stats_original.algebraic_connectivity,
+    ) {
+        (Some(connectivity), Some(original)) => writeln!(
+            o,
+            "Algebraic connectivity: {} ({:.3}%)",
+            connectivity - original,
+            percentage_change(original, connectivity)
+        )?,
+        _ => writeln!(o, "Algebraic connectivity: not converged")?,
+    }
+    writeln!(o, "\nDegree measures:")?;
     writeln!(
         o,
         "Average: {} ({:.3}%)",
         stats.degree_average - stats_original.degree_average,
         percentage_change(stats_original.degree_average, stats.degree_average)
-    )
-    .unwrap();
+    )?;
     writeln!(
         o,
         "Median: {} ({:.3}%)",
         stats.degree_median - stats_original.degree_median,
         percentage_change(stats_original.degree_median, stats.degree_median)
-    )
-    .unwrap();
+    )?;
     writeln!(
         o,
         "Min: {} ({:.3}%), max: {} ({:.3}%), delta: {} ({:.3}%)",
@@ -195,10 +298,9 @@ pub fn print_statistics_delta(
             stats_original.degree_max - stats_original.degree_min,
             stats.degree_max - stats.degree_min
         )
-    )
-    .unwrap();
+    )?;
 
-    writeln!(o, "\nBetweenness measures:").unwrap();
+    writeln!(o, "\nBetweenness measures:")?;
     writeln!(
         o,
         "Average: {} ({:.3}%)",
@@ -207,15 +309,13 @@ pub fn print_statistics_delta(
             stats_original.betweenness_average,
             stats.betweenness_average
         )
-    )
-    .unwrap();
+    )?;
     writeln!(
         o,
         "Median: {} ({:.3}%)",
         stats.betweenness_median - stats_original.betweenness_median,
         percentage_change(stats_original.betweenness_median, stats.betweenness_median)
-    )
-    .unwrap();
+    )?;
     writeln!(
         o,
         "Min: {} ({:.3}%), max: {} ({:.3}%), delta: {} ({:.3}%)",
@@ -230,24 +330,21 @@ pub fn print_statistics_delta(
             stats_original.betweenness_max - stats_original.betweenness_min,
             stats.betweenness_max - stats.betweenness_min
         )
-    )
-    .unwrap();
+    )?;
 
-    writeln!(o, "\nCloseness measures:").unwrap();
+    writeln!(o, "\nCloseness measures:")?;
     writeln!(
         o,
         "Average: {} ({:.3}%)",
         stats.closeness_average - stats_original.closeness_average,
         percentage_change(stats_original.closeness_average, stats.closeness_average)
-    )
-    .unwrap();
+    )?;
     writeln!(
         o,
         "Median: {} ({:.3}%)",
         stats.closeness_median - stats_original.closeness_median,
         percentage_change(stats_original.closeness_median, stats.closeness_median)
-    )
-    .unwrap();
+    )?;
     writeln!(
         o,
         "Min: {} ({:.3}%), max: {} ({:.3}%), delta: {} ({:.3}%)",
@@ -262,10 +359,9 @@ pub fn print_statistics_delta(
             stats_original.closeness_max - stats_original.closeness_min,
             stats.closeness_max - stats.closeness_min
         )
-    )
-    .unwrap();
+    )?;
 
-    writeln!(o, "\nEigenvector measures:").unwrap();
+    writeln!(o, "\nEigenvector measures:")?;
     writeln!(
         o,
         "Average: {} ({:.3}%)",
@@ -274,15 +370,13 @@ pub fn print_statistics_delta(
             stats_original.eigenvector_average,
             stats.eigenvector_average
         )
-    )
-    .unwrap();
+    )?;
     writeln!(
         o,
         "Median: {} ({:.3}%)",
         stats.eigenvector_median - stats_original.eigenvector_median,
         percentage_change(stats_original.eigenvector_median, stats.eigenvector_median)
-    )
-    .unwrap();
+    )?;
     writeln!(
         o,
         "Min: {} ({:.3}%), max: {} ({:.3}%), delta: {} ({:.3}%)",
@@ -297,10 +391,125 @@ pub fn print_statistics_delta(
             stats_original.eigenvector_max - stats_original.eigenvector_min,
             stats.eigenvector_max - stats.eigenvector_min
         )
-    )
-    .unwrap();
+    )?;
 
-    writeln!(o, "----------------------------------------\n").unwrap();
+    writeln!(o, "\nClustering measures:")?;
+    writeln!(
+        o,
+        "Average: {} ({:.3}%)",
+        stats.clustering_average - stats_original.clustering_average,
+        percentage_change(stats_original.clustering_average, stats.clustering_average)
+    )?;
+    writeln!(
+        o,
+        "Median: {} ({:.3}%)",
+        stats.clustering_median - stats_original.clustering_median,
+        percentage_change(stats_original.clustering_median, stats.clustering_median)
+    )?;
+    writeln!(
+        o,
+        "Min: {} ({:.3}%), max: {} ({:.3}%), delta: {} ({:.3}%)",
+        stats.clustering_min - stats_original.clustering_min,
+        percentage_change(stats_original.clustering_min, stats.clustering_min),
+        stats.clustering_max - stats_original.clustering_max,
+        percentage_change(stats_original.clustering_max, stats.clustering_max),
+        stats.clustering_max
+            - stats.clustering_min
+            - (stats_original.clustering_max - stats_original.clustering_min),
+        percentage_change(
+            stats_original.clustering_max - stats_original.clustering_min,
+            stats.clustering_max - stats.clustering_min
+        )
+    )?;
+    writeln!(
+        o,
+        "Transitivity: {} ({:.3}%)",
+        stats.transitivity - stats_original.transitivity,
+        percentage_change(stats_original.transitivity, stats.transitivity)
+    )?;
+
+    writeln!(o, "\nDistance measures:")?;
+    writeln!(
+        o,
+        "Average eccentricity: {} ({:.3}%)",
+        stats.eccentricity_average - stats_original.eccentricity_average,
+        percentage_change(
+            stats_original.eccentricity_average,
+            stats.eccentricity_average
+        )
+    )?;
+    writeln!(
+        o,
+        "Diameter: {} ({:.3}%), radius: {} ({:.3}%)",
+        stats.diameter as i64 - stats_original.diameter as i64,
+        percentage_change(stats_original.diameter as f64, stats.diameter as f64),
+        stats.radius as i64 - stats_original.radius as i64,
+        percentage_change(stats_original.radius as f64, stats.radius as f64)
+    )?;
+    writeln!(
+        o,
+        "Average path length: {} ({:.3}%)",
+        stats.average_path_length - stats_original.average_path_length,
+        percentage_change(
+            stats_original.average_path_length,
+            stats.average_path_length
+        )
+    )?;
+    writeln!(o, "Hop distribution:")?;
+    let max_hops = stats
+        .hop_distribution
+        .len()
+        .max(stats_original.hop_distribution.len());
+    for hops in 1..max_hops {
+        let count = stats
+            .hop_distribution
+            .get(hops)
+            .copied()
+            .unwrap_or_default();
+        let count_original = stats_original
+            .hop_distribution
+            .get(hops)
+            .copied()
+            .unwrap_or_default();
+        writeln!(
+            o,
+            "{hops} hops: {} ({:.3}%)",
+            count as i64 - count_original as i64,
+            percentage_change(count_original as f64, count as f64)
+        )?;
+    }
+
+    writeln!(o, "\nCommunity measures:")?;
+    writeln!(
+        o,
+        "Communities: {} ({:.3}%), modularity: {} ({:.3}%)",
+        stats.communities_count as i64 - stats_original.communities_count as i64,
+        percentage_change(
+            stats_original.communities_count as f64,
+            stats.communities_count as f64
+        ),
+        stats.modularity - stats_original.modularity,
+        percentage_change(stats_original.modularity, stats.modularity)
+    )?;
+
+    if let (Some(average), Some(high_risk_nodes), Some(original_average), Some(original_high)) = (
+        stats.eclipse_risk_average,
+        stats.high_eclipse_risk_nodes,
+        stats_original.eclipse_risk_average,
+        stats_original.high_eclipse_risk_nodes,
+    ) {
+        writeln!(o, "\nEclipse risk measures:")?;
+        writeln!(
+            o,
+            "Average: {} ({:.3}%), high risk nodes: {} ({:.3}%)",
+            average - original_average,
+            percentage_change(original_average, average),
+            high_risk_nodes as i64 - original_high as i64,
+            percentage_change(original_high as f64, high_risk_nodes as f64)
+        )?;
+    }
+
+    writeln!(o, "----------------------------------------\n")
 }
 
 /// Measures the average degree of the graph.
@@ -330,17 +539,42 @@ where
         return None;
     }
 
-    let mut list = list.to_vec();
-    list.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let mut list = list
+        .iter()
+        .map(|value| (*value).into())
+        .collect::<Vec<f64>>();
+    list.sort_by(f64::total_cmp);
 
     let mid = list.len() / 2;
     if list.len() % 2 == 0 {
-        Some((list[mid - 1].into() + list[mid].into()) / 2.0)
+        Some((list[mid - 1] + list[mid]) / 2.0)
     } else {
-        Some(list[mid].into())
+        Some(list[mid])
     }
 }
 
+/// Computes percentile (0-100) of any numeric type convertible to float value, interpolating
+/// between the closest ranks - the 50th percentile is the median.
+pub fn percentile<T>(list: &[T], percentile: f64) -> Option<f64>
+where
+    T: PartialOrd + Into<f64> + Copy,
+{
+    if list.is_empty() {
+        return None;
+    }
+
+    let mut list = list
+        .iter()
+        .map(|value| (*value).into())
+        .collect::<Vec<f64>>();
+    list.sort_by(f64::total_cmp);
+
+    let rank = percentile.clamp(0.0, 100.0) / 100.0 * (list.len() - 1) as f64;
+    let lower = list[rank.floor() as usize];
+    let upper = list[rank.ceil() as usize];
+    Some(lower + (upper - lower) * rank.fract())
+}
+
 #[cfg(test)]
 mod tests {
     use std::{
@@ -415,4 +649,14 @@ mod tests {
         let list = Vec::<f64>::new();
         assert!(median(&list).is_none());
     }
+
+    #[test]
+    fn percentile_test() {
+        let list = vec![4, 1, 3, 2, 5, 6];
+        assert_eq!(percentile(&list, 0.0).unwrap(), 1.0);
+        assert_eq!(percentile(&list, 50.0).unwrap(), median(&list).unwrap());
+        assert_eq!(percentile(&list, 80.0).unwrap(), 5.0);
+        assert_eq!(percentile(&list, 100.0).unwrap(), 6.0);
+        assert!(percentile(&Vec::<f64>::new(), 50.0).is_none());
+    }
 }
@@ -0,0 +1,158 @@
+//! k-core decomposition.
+//!
+//! k-core is the biggest subgraph where every node has at least `k` connections inside of it.
+//! Core number of the node is the highest `k` for which the node belongs to the k-core - nodes
+//! with high core numbers form the resilient core of the network and the ones with low numbers
+//! hang on its periphery.
+
+use serde::{Deserialize, Serialize};
+
+/// Size of a single k-core.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CoreLevel {
+    /// Minimal number of connections inside the core
+    pub k: usize,
+    /// Number of nodes in the k-core
+    pub size: usize,
+}
+
+/// Core-periphery classification of the network.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CoreProfile {
+    /// Nodes with core number at least this value form the core, others are the periphery
+    pub threshold: usize,
+    /// Size of every k-core, from 0 up to the highest core number
+    pub levels: Vec<CoreLevel>,
+}
+
+impl CoreProfile {
+    /// Build profile from the nodes core numbers. If threshold is not given, only the main core
+    /// (the one with highest k) is treated as the core.
+    pub fn new(core_numbers: &[usize], threshold: Option<usize>) -> Self {
+        let max_core = core_numbers.iter().copied().max().unwrap_or_default();
+
+        let mut shell_sizes = vec![0; max_core + 1];
+        for core_number in core_numbers {
+            shell_sizes[*core_number] += 1;
+        }
+
+        // k-core contains all shells with at least k.
+        let mut size = 0;
+        let mut levels = shell_sizes
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(k, shell_size)| {
+                size += shell_size;
+                CoreLevel { k, size }
+            })
+            .collect::<Vec<_>>();
+        levels.reverse();
+
+        CoreProfile {
+            threshold: threshold.unwrap_or(max_core),
+            levels,
+        }
+    }
+
+    /// Check if node with given core number belongs to the core.
+    pub fn is_core(&self, core_number: usize) -> bool {
+        core_number >= self.threshold
+    }
+}
+
+/// Compute core number of every node of the undirected graph given as an adjacency list
+/// (without duplicates). Uses Batagelj and Zaversnik algorithm - nodes are peeled in order of
+/// their current degree kept in buckets, which takes linear time.
+pub fn core_numbers(adjacency: &[Vec<usize>]) -> Vec<usize> {
+    let nodes = adjacency.len();
+    let mut degree = adjacency
+        .iter()
+        .map(|peers| peers.len())
+        .collect::<Vec<_>>();
+    let max_degree = degree.iter().copied().max().unwrap_or_default();
+
+    // Nodes sorted by degree, with start position of every degree bucket.
+    let mut bucket_start = vec![0; max_degree + 1];
+    for d in &degree {
+        bucket_start[*d] += 1;
+    }
+    let mut start = 0;
+    for count in bucket_start.iter_mut() {
+        let size = *count;
+        *count = start;
+        start += size;
+    }
+
+    let mut order = vec![0; nodes];
+    let mut position = vec![0; nodes];
+    let mut next_free = bucket_start.clone();
+    for node in 0..nodes {
+        position[node] = next_free[degree[node]];
+        order[position[node]] = node;
+        next_free[degree[node]] += 1;
+    }
+
+    for i in 0..nodes {
+        let node = order[i];
+        for &peer in &adjacency[node] {
+            if degree[peer] <= degree[node] {
+                continue;
+            }
+
+            // Move the peer to the beginning of its bucket and shrink the bucket, so the peer
+            // lands in the bucket of one lower degree.
+            let peer_degree = degree[peer];
+            let first_position = bucket_start[peer_degree];
+            let first_node = order[first_position];
+            if first_node != peer {
+                order.swap(position[peer], first_position);
+                position[first_node] = position[peer];
+                position[peer] = first_position;
+            }
+            bucket_start[peer_degree] += 1;
+            degree[peer] -= 1;
+        }
+    }
+
+    degree
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn core_numbers_test() {
+        // Clique 0, 1, 2, 3 with a triangle 3, 4, 5 attached, node 6 hanging on node 5 and
+        // isolated node 7.
+        let adjacency = vec![
+            vec![1, 2, 3],
+            vec![0, 2, 3],
+            vec![0, 1, 3],
+            vec![0, 1, 2, 4, 5],
+            vec![3, 5],
+            vec![3, 4, 6],
+            vec![5],
+            vec![],
+        ];
+
+        let core_numbers = core_numbers(&adjacency);
+        assert_eq!(core_numbers, vec![3, 3, 3, 3, 2, 2, 1, 0]);
+
+        let profile = CoreProfile::new(&core_numbers, None);
+        assert_eq!(profile.threshold, 3);
+        assert_eq!(
+            profile.levels,
+            vec![
+                CoreLevel { k: 0, size: 8 },
+                CoreLevel { k: 1, size: 7 },
+                CoreLevel { k: 2, size: 6 },
+                CoreLevel { k: 3, size: 4 },
+            ]
+        );
+        assert!(profile.is_core(3));
+        assert!(!profile.is_core(2));
+        assert_eq!(CoreProfile::new(&core_numbers, Some(2)).threshold, 2);
+    }
+}
@@ -0,0 +1,528 @@
+//! P2P network crawler data cruncher for graph metrics.
+//!
+//! Crunchy takes a sample produced by the crawler, computes graph metrics for every node and runs
+//! Intelligent Peer Sharing (IPS) to recommend better peer lists. The whole pipeline is exposed
+//! here, so it can be embedded in other tools:
+//!
+//! 1. load a crawler sample with [`load_response`],
+//! 2. build nodes, compute their metrics and histograms with [`generate_state`],
+//! 3. run IPS for a chosen network with [`run_ips`],
+//! 4. serialize results with [`save_state`] and [`save_peers`].
+//!
+//! A previously written state can be read back with [`load_state`] and peer lists with
+//! [`load_peers`]. All fallible operations report [`CrunchyError`].
+
+pub mod centrality;
+pub mod clustering;
+pub mod community;
+pub mod config;
+pub mod distance;
+pub mod eclipse;
+pub mod error;
+pub mod geoip_cache;
+mod histogram;
+pub mod ips;
+pub mod kcore;
+pub mod nodes;
+pub mod percolation;
+pub mod spectral;
+pub mod timings;
+
+use std::{fs, io::Write, path::Path, time::Instant};
+
+use serde::{Deserialize, Serialize};
+use ziggurat_core_crawler::summary::{NetworkSummary, NetworkType};
+
+use crate::{
+    centrality::CentralityApproximation,
+    clustering::ClusteringSummary,
+    community::CommunitySummary,
+    config::{AnalysisConfiguration, CrunchyConfiguration},
+    distance::DistanceSummary,
+    eclipse::EclipseSummary,
+    geoip_cache::GeoIPCache,
+    ips::{
+        algorithm::Ips,
+        config::IPSConfiguration,
+        graph_utils::{find_exact_bridges, BridgeAnalysis},
+        peer::Peer,
+    },
+    kcore::CoreProfile,
+    nodes::{
+        analyze_clustering, analyze_communities, analyze_components, analyze_cores,
+        analyze_distances, analyze_eclipse_risk, create_histograms, create_nodes, graph_hash,
+        ComponentSummary,
+    },
+    percolation::{nodes_from_peers, simulate, PercolationCurve},
+    timings::Timings,
+};
+pub use crate::{
+    error::{CrunchyError, Result},
+    nodes::{HistogramSummary, Node},
+};
+
+/// Processed network state - the main output of crunchy, directly usable by the renderer.
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct CrunchyState {
+    /// Time spent on processing the sample (in seconds) - sum of all recorded phases
+    pub elapsed: f64,
+    /// Duration of each processing phase
+    #[serde(default)]
+    pub timings: Timings,
+    /// Nodes with their computed metrics
+    pub nodes: Vec<Node>,
+    /// Histograms of the node metrics
+    pub histograms: Vec<HistogramSummary>,
+    /// Transitivity and average clustering of the network
+    #[serde(default)]
+    pub clustering: ClusteringSummary,
+    /// Core-periphery classification with sizes of all k-cores
+    #[serde(default)]
+    pub core_profile: CoreProfile,
+    /// Diameter, radius, average path length and hop distribution of the network
+    #[serde(default)]
+    pub distances: DistanceSummary,
+    /// Modularity and sizes of the communities found in the network
+    #[serde(default)]
+    pub communities: CommunitySummary,
+    /// Eclipse risk figures and the most endangered nodes of the network
+    #[serde(default)]
+    pub eclipse: EclipseSummary,
+    /// Connected components (islands) of the network, biggest first
+    #[serde(default)]
+    pub components: Vec<ComponentSummary>,
+    /// Bridges, articulation points and 2-edge-connected components of the graph
+    #[serde(default)]
+    pub bridge_analysis: BridgeAnalysis,
+    /// Parameters of the centralities approximation (none if they were computed exactly)
+    #[serde(default)]
+    pub centrality_approximation: Option<CentralityApproximation>,
+    /// Content hash of the graph the metrics were computed for (empty if unknown)
+    #[serde(default)]
+    pub graph_hash: String,
+}
+
+impl CrunchyState {
+    /// Append durations of the phases run for the given network (e.g. returned by [`run_ips`]).
+    pub fn add_network_timings(&mut self, timings: &Timings, network: NetworkType) {
+        self.timings.extend_for_network(timings, network);
+        self.elapsed = self.timings.total();
+    }
+
+    /// Check that connections of every node point to existing nodes.
+    pub fn validate(&self) -> Result<()> {
+        for node in &self.nodes {
+            if let Some(peer) = node
+                .connections
+                .iter()
+                .find(|peer| **peer >= self.nodes.len())
+            {
+                return Err(CrunchyError::Topology(format!(
+                    "{} is connected to node {peer}, but there are only {} nodes",
+                    node.addr,
+                    self.nodes.len()
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Crawler's JSON-RPC response containing the network sample.
+#[derive(Default, Deserialize)]
+pub struct JsonRpcResponse {
+    /// JSON-RPC version
+    pub jsonrpc: String,
+    /// Network sample
+    pub result: NetworkSummary,
+    /// Request identifier
+    pub id: usize,
+}
+
+/// Load crawler sample from the file.
+pub fn load_response(filepath: &str) -> Result<JsonRpcResponse> {
+    let jstring = fs::read_to_string(filepath)
+        .map_err(|e| CrunchyError::io(format!("could not open sample file {filepath}"), e))?;
+    serde_json::from_str(&jstring)
+        .map_err(|e| CrunchyError::parse(format!("could not parse sample file {filepath}"), e))
+}
+
+/// Load previously saved state from the file. Connections of the nodes are validated, so a
+/// corrupted state is reported as a topology error.
+pub fn load_state(filepath: &str) -> Result<CrunchyState> {
+    let jstring = fs::read_to_string(filepath)
+        .map_err(|e| CrunchyError::io(format!("could not open state file {filepath}"), e))?;
+    let state: CrunchyState = serde_json::from_str(&jstring)
+        .map_err(|e| CrunchyError::parse(format!("could not parse state file {filepath}"), e))?;
+    state.validate()?;
+    Ok(state)
+}
+
+/// Load peer lists previously written by IPS.
+pub fn load_peers(filepath: &str) -> Result<Vec<Peer>> {
+    let jstring = fs::read_to_string(filepath)
+        .map_err(|e| CrunchyError::io(format!("could not open peer file {filepath}"), e))?;
+    serde_json::from_str(&jstring)
+        .map_err(|e| CrunchyError::parse(format!("could not parse peer file {filepath}"), e))
+}
+
+/// What happened to the GeoIP cache while generating the state. Nothing of it is fatal, so it's
+/// up to the caller whether to report it.
+#[derive(Debug, Default)]
+pub struct GeoCacheStatus {
+    /// There was no cache file to load, a new one is created
+    pub created: bool,
+    /// Error of saving the cache (geolocations fetched now are looked up again next time)
+    pub save_error: Option<CrunchyError>,
+}
+
+/// Load the sample pointed by the configuration, geolocate nodes and compute their metrics.
+/// GeoIP cache is loaded before and saved after the geolocation.
+pub async fn generate_state(
+    config: &CrunchyConfiguration,
+) -> Result<(CrunchyState, GeoCacheStatus)> {
+    let input_file_path = config
+        .input_file_path
+        .as_ref()
+        .ok_or_else(|| CrunchyError::Config("no input file set".to_owned()))?;
+
+    let mut timings = Timings::default();
+    let mut geo_cache = GeoIPCache::new(&config.geoip_config);
+    let response = timings.measure("sample_load", || {
+        load_response(&input_file_path.display().to_string())
+    })?;
+
+    // Missing cache is not a problem, corrupted one is.
+    let mut cache_status = GeoCacheStatus {
+        created: !geo_cache.load().await?,
+        ..Default::default()
+    };
+
+    geo_cache.configure_providers(&config.geoip_config)?;
+
+    let mut nodes = create_nodes(
+        config.network_type_filter,
+        &response.result.nodes_indices,
+        &response.result.node_addrs,
+        &response.result.node_network_types,
+        &geo_cache,
+        &config.analysis_config,
+        config.num_threads,
+        &mut timings,
+    )
+    .await;
+
+    let clustering = timings.measure("clustering", || analyze_clustering(&mut nodes));
+    let core_profile = timings.measure("cores", || {
+        analyze_cores(&mut nodes, config.analysis_config.core_threshold)
+    });
+    let distances = timings.measure("distances", || {
+        analyze_distances(
+            &mut nodes,
+            config.analysis_config.distance_sample_size,
+            config.analysis_config.seed,
+            config.num_threads,
+        )
+    });
+    let communities = timings.measure("communities", || {
+        analyze_communities(
+            &mut nodes,
+            config.analysis_config.community_algorithm,
+            config.analysis_config.seed,
+        )
+    });
+    let eclipse = timings.measure("eclipse", || {
+        analyze_eclipse_risk(&mut nodes, config.num_threads)
+    });
+    let components = timings.measure("components", || analyze_components(&mut nodes));
+    let bridge_analysis = timings.measure("bridges", || find_exact_bridges(&nodes));
+
+    let start = Instant::now();
+    let histograms = create_histograms(&nodes).await;
+    timings.record("histograms", start.elapsed());
+
+    // Save all changes done to the cache - the state is usable even if it fails.
+    cache_status.save_error = geo_cache.save().await.err();
+
+    let state = CrunchyState {
+        elapsed: timings.total(),
+        centrality_approximation: CentralityApproximation::from_config(
+            &config.analysis_config,
+            nodes.len(),
+        ),
+        timings,
+        graph_hash: graph_hash(&nodes),
+        nodes,
+        histograms,
+        clustering,
+        core_profile,
+        distances,
+        communities,
+        eclipse,
+        components,
+        bridge_analysis,
+    };
+
+    Ok((state, cache_status))
+}
+
+/// Run Intelligent Peer Sharing for the given network and return new peer lists together with
+/// durations of the IPS phases. The durations can be added to the state timings with
+/// [`CrunchyState::add_network_timings`].
+pub async fn run_ips(
+    state: &CrunchyState,
+    ips_config: &IPSConfiguration,
+    analysis_config: &AnalysisConfiguration,
+    network: NetworkType,
+    num_threads: usize,
+) -> Result<(Vec<Peer>, Timings)> {
+    let mut ips = Ips::new(ips_config.clone(), analysis_config.clone());
+    let peers = ips.generate(state, network, num_threads).await?;
+
+    Ok((peers, ips.timings().clone()))
+}
+
+/// Run percolation simulation for every configured strategy. Networks are built from the
+/// configured peer list files (with geolocation taken from the state), so e.g. vanilla and IPS
+/// peer lists can be compared. If there are no peer list files, the state network is used.
+pub fn run_percolation(
+    state: &CrunchyState,
+    config: &CrunchyConfiguration,
+) -> Result<Vec<PercolationCurve>> {
+    let percolation_config = &config.percolation_config;
+    let networks = if percolation_config.peer_file_paths.is_empty() {
+        vec![("state".to_owned(), state.nodes.clone())]
+    } else {
+        percolation_config
+            .peer_file_paths
+            .iter()
+            .map(|path| {
+                let peers = load_peers(&path.display().to_string())?;
+                Ok((
+                    path.display().to_string(),
+                    nodes_from_peers(&peers, &state.nodes),
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?
+    };
+
+    let mut curves = Vec::new();
+    for (label, nodes) in &networks {
+        for strategy in &percolation_config.strategies {
+            curves.push(simulate(
+                label,
+                nodes,
+                *strategy,
+                percolation_config,
+                &config.analysis_config,
+                config.num_threads,
+            ));
+        }
+    }
+
+    Ok(curves)
+}
+
+/// Print statistics of the network stored in the state. Centralities stored in the state are
+/// reused and hop distances are measured from a bounded sample of nodes, so it's cheap even for
+/// big networks. Analysis settings (seed, distance sample size, community algorithm, core
+/// threshold) are taken from the configuration, so the figures match the ones printed by IPS.
+pub fn print_state_statistics(
+    state: &CrunchyState,
+    config: &CrunchyConfiguration,
+    output: &mut Box<dyn Write>,
+) -> Result<()> {
+    let write_error = |e| CrunchyError::io("could not write statistics", e);
+
+    writeln!(output, "Nodes per network:").map_err(write_error)?;
+    for network in networks_in_sample(&state.nodes) {
+        let count = state
+            .nodes
+            .iter()
+            .filter(|node| node.network_type == network)
+            .count();
+        writeln!(output, "{network:?}: {count}").map_err(write_error)?;
+    }
+
+    writeln!(output, "Connected components: {}", state.components.len()).map_err(write_error)?;
+    for component in &state.components {
+        writeln!(
+            output,
+            "Component {}: {} nodes ({:.3}%)",
+            component.id,
+            component.size,
+            component.share * 100.0
+        )
+        .map_err(write_error)?;
+    }
+
+    writeln!(output, "Statistics for the whole network:").map_err(write_error)?;
+    Ips::new(config.ips_config.clone(), config.analysis_config.clone())
+        .print_network_statistics(&state.nodes, output)
+}
+
+/// Collect all network types present in the state (in order of first appearance).
+pub fn networks_in_sample(nodes: &[Node]) -> Vec<NetworkType> {
+    let mut networks = Vec::new();
+    for node in nodes {
+        // Invalid type marks nodes that couldn't be classified - there is no network to work on.
+        if node.network_type != NetworkType::Invalid && !networks.contains(&node.network_type) {
+            networks.push(node.network_type);
+        }
+    }
+    networks
+}
+
+/// Write state to the file as JSON. Serialization time is included in the written timings.
+pub fn save_state(state: &CrunchyState, path: &Path) -> Result<()> {
+    let start = Instant::now();
+    let mut joutput = serde_json::to_value(state)
+        .map_err(|e| CrunchyError::parse("could not serialize state", e))?;
+    let mut timings = state.timings.clone();
+    timings.record("serialization", start.elapsed());
+
+    // Timings are tiny compared to the rest of the state, so just replace them in the already
+    // serialized value to include the serialization phase itself.
+    joutput["timings"] = serde_json::to_value(&timings)
+        .map_err(|e| CrunchyError::parse("could not serialize timings", e))?;
+    joutput["elapsed"] = timings.total().into();
+
+    fs::write(path, joutput.to_string())
+        .map_err(|e| CrunchyError::io(format!("could not write state file {}", path.display()), e))
+}
+
+/// Write peer lists to the file as JSON.
+pub fn save_peers(peers: &[Peer], path: &Path) -> Result<()> {
+    let peerlist = serde_json::to_string(peers)
+        .map_err(|e| CrunchyError::parse("could not serialize peer list", e))?;
+    fs::write(path, peerlist)
+        .map_err(|e| CrunchyError::io(format!("could not write peer file {}", path.display()), e))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::{net::SocketAddr, thread};
+
+    use super::*;
+    use crate::config::GeoIPConfiguration;
+
+    #[tokio::test]
+    async fn create_nodes_unfiltered_test() {
+        let response = load_response("testdata/sample.json").unwrap();
+
+        let config = GeoIPConfiguration::default();
+        let mut geo_cache = GeoIPCache::new(&config);
+        geo_cache.configure_providers(&config).unwrap();
+
+        let num_threads = thread::available_parallelism().unwrap().get();
+        let nodes = create_nodes(
+            None,
+            &response.result.nodes_indices,
+            &response.result.node_addrs,
+            &response.result.node_network_types,
+            &geo_cache,
+            &AnalysisConfiguration::default(),
+            num_threads,
+            &mut Timings::default(),
+        )
+        .await;
+
+        assert_eq!(nodes.len(), 6103);
+        assert_eq!(nodes[0].connections.len(), 2478);
+        assert_eq!(nodes[1].connections.len(), 2216);
+        assert_eq!(nodes[2].connections.len(), 1);
+        assert_eq!(nodes[3].connections.len(), 2184);
+        assert_eq!(nodes[3].connections[2], 609);
+    }
+
+    #[tokio::test]
+    async fn create_nodes_filtered_test1() {
+        let indices = vec![vec![1, 2], vec![0, 2, 3], vec![0, 1, 3], vec![1, 2]];
+        let node_addrs = vec![
+            SocketAddr::from(([127, 0, 0, 1], 1234)),
+            SocketAddr::from(([127, 0, 0, 2], 1234)),
+            SocketAddr::from(([127, 0, 0, 3], 1234)),
+            SocketAddr::from(([127, 0, 0, 4], 1234)),
+        ];
+        let node_network_types = vec![
+            NetworkType::Unknown,
+            NetworkType::Zcash,
+            NetworkType::Unknown,
+            NetworkType::Zcash,
+        ];
+        let config = GeoIPConfiguration::default();
+        let mut geo_cache = GeoIPCache::new(&config);
+        geo_cache.configure_providers(&config).unwrap();
+
+        let num_threads = thread::available_parallelism().unwrap().get();
+        let nodes = create_nodes(
+            Some(NetworkType::Zcash),
+            &indices,
+            &node_addrs,
+            &node_network_types,
+            &geo_cache,
+            &AnalysisConfiguration::default(),
+            num_threads,
+            &mut Timings::default(),
+        )
+        .await;
+        assert_eq!(nodes.len(), 2);
+        assert_eq!(nodes[0].connections, vec![1]);
+        assert_eq!(nodes[1].connections, vec![0]);
+    }
+
+    #[tokio::test]
+    async fn create_nodes_filtered_test2() {
+        let response = load_response("testdata/sample.json").unwrap();
+
+        let config = GeoIPConfiguration::default();
+        let mut geo_cache = GeoIPCache::new(&config);
+        geo_cache.configure_providers(&config).unwrap();
+
+        let num_threads = thread::available_parallelism().unwrap().get();
+        let nodes = create_nodes(
+            Some(NetworkType::Zcash),
+            &response.result.nodes_indices,
+            &response.result.node_addrs,
+            &response.result.node_network_types,
+            &geo_cache,
+            &AnalysisConfiguration::default(),
+            num_threads,
+            &mut Timings::default(),
+        )
+        .await;
+        assert_eq!(nodes.len(), 122);
+        assert_eq!(nodes[0].connections.len(), 2);
+        assert_eq!(nodes[1].connections.len(), 0);
+        assert_eq!(nodes[2].connections.len(), 1);
+        assert_eq!(nodes[3].connections.len(), 1);
+        assert_eq!(nodes[3].connections[0], 56);
+
+        let node = nodes[0].clone();
+        assert_eq!(node.addr.to_string(), "3.72.134.66:8233");
+        let epsilon: f64 = 0.0000001;
+        assert!((node.betweenness - 47.525898078529664).abs() < epsilon);
+        assert!((node.closeness - 1.603305785123967).abs() < epsilon);
+    }
+
+    #[test]
+    fn validate_state_test() {
+        let mut state = CrunchyState {
+            nodes: (0..2)
+                .map(|i| Node {
+                    addr: format!("10.0.0.{i}:8233").parse::<SocketAddr>().unwrap(),
+                    connections: vec![1 - i],
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        };
+        assert!(state.validate().is_ok());
+
+        // Hand-edited state pointing past the nodes.
+        state.nodes[1].connections.push(2);
+        assert!(matches!(state.validate(), Err(CrunchyError::Topology(_))));
+    }
+}
@@ -1,106 +1,287 @@
-mod config;
-mod geoip_cache;
-mod histogram;
-mod ips;
-mod nodes;
-
-use std::{fs, path::PathBuf, time::Instant};
-
-use clap::Parser;
-use serde::{Deserialize, Serialize};
-use ziggurat_core_crawler::summary::{NetworkSummary, NetworkType};
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
 
-use crate::{
+use clap::{Parser, Subcommand};
+use ziggurat_core_crawler::summary::NetworkType;
+use ziggurat_crunchy::{
     config::CrunchyConfiguration,
+    generate_state,
     geoip_cache::GeoIPCache,
-    ips::algorithm::Ips,
-    nodes::{create_histograms, create_nodes, HistogramSummary, Node},
+    ips::{
+        peer::{PeerChurn, PeerDiff},
+        simulation::{save_rounds, simulate_rounds},
+    },
+    load_peers, load_response, load_state, networks_in_sample,
+    percolation::save_curves,
+    print_state_statistics, run_ips, run_percolation, save_peers, save_state, CrunchyError,
+    CrunchyState, Result,
 };
 
-#[derive(Default, Clone, Serialize, Deserialize)]
-pub struct CrunchyState {
-    elapsed: f64,
-    nodes: Vec<Node>,
-    histograms: Vec<HistogramSummary>,
+/// Get output path from the configuration or report which one is missing.
+fn output_path<'a>(path: &'a Option<PathBuf>, name: &str) -> Result<&'a Path> {
+    path.as_deref()
+        .ok_or_else(|| CrunchyError::Config(format!("no {name} set")))
 }
 
-#[allow(dead_code)]
-#[derive(Default, Deserialize)]
-pub struct JsonRpcResponse {
-    jsonrpc: String,
-    result: NetworkSummary,
-    id: usize,
-}
+/// Run IPS for configured network(s) over the state and write peer lists.
+async fn write_peers(config: &CrunchyConfiguration, state: &mut CrunchyState) -> Result<()> {
+    let networks = if config.ips_config.all_networks {
+        networks_in_sample(&state.nodes)
+    } else {
+        vec![config.ips_config.network()]
+    };
 
-pub fn load_response(filepath: &str) -> JsonRpcResponse {
-    let jstring = fs::read_to_string(filepath).expect("could not open response file");
-    serde_json::from_str(&jstring).unwrap()
-}
+    let mut processed = Vec::with_capacity(networks.len());
+    for network in networks {
+        let ips_config = if config.ips_config.all_networks {
+            config.ips_config.for_network(network)
+        } else {
+            config.ips_config.clone()
+        };
+
+        let peer_file_path = output_path(&ips_config.peer_file_path, "peer file path")?;
+        let (ips_peers, timings) = run_ips(
+            state,
+            &ips_config,
+            &config.analysis_config,
+            network,
+            config.num_threads,
+        )
+        .await?;
+        state.add_network_timings(&timings, network);
+        save_peers(&ips_peers, peer_file_path)?;
+
+        processed.push((
+            network,
+            ips_peers.len(),
+            ips_config.desired_degree,
+            peer_file_path.to_path_buf(),
+        ));
+    }
 
-pub fn load_state(filepath: &str) -> CrunchyState {
-    let jstring = fs::read_to_string(filepath).expect("could not open state file");
-    serde_json::from_str(&jstring).unwrap()
+    println!("IPS summary:");
+    for (network, nodes_count, desired_degree, peer_file_path) in processed {
+        println!(
+            "{network:?}: {nodes_count} nodes, desired degree policy {desired_degree:?}, peer list written to {}",
+            peer_file_path.display()
+        );
+    }
+
+    Ok(())
 }
 
 /// Perform all the necessary steps to generate the state file and the peer list.
-async fn write_state(config: &CrunchyConfiguration) {
-    let mut geo_cache = GeoIPCache::new(&config.geoip_config);
-    let response = load_response(config.input_file_path.as_ref().unwrap().to_str().unwrap());
-    let start = Instant::now();
-    let elapsed = start.elapsed();
+async fn write_state(config: &CrunchyConfiguration) -> Result<()> {
+    let mut state = generate_state_reporting(config).await?;
+    write_peers(config, &mut state).await?;
+    save_state(
+        &state,
+        output_path(&config.state_file_path, "state file path")?,
+    )
+}
 
-    let res = geo_cache.load().await;
-    if res.is_err() {
-        println!("No cache file to load! Will be created one.");
+/// Generate the state and report what happened to the GeoIP cache.
+async fn generate_state_reporting(config: &CrunchyConfiguration) -> Result<CrunchyState> {
+    let (state, cache_status) = generate_state(config).await?;
+    if cache_status.created {
+        println!("No cache file to load, a new one will be created.");
     }
+    // Not fatal - geolocations missing in the cache are fetched again next time.
+    if let Some(err) = cache_status.save_error {
+        eprintln!("warning: {}", err.report().trim_start_matches("error: "));
+    }
+    Ok(state)
+}
 
-    geo_cache.configure_providers(&config.geoip_config);
-
-    let nodes = create_nodes(
-        config.network_type_filter,
-        &response.result.nodes_indices,
-        &response.result.node_addrs,
-        &response.result.node_network_types,
-        &geo_cache,
-        config.num_threads,
+/// Generate the state file only.
+async fn crunch(config: &CrunchyConfiguration) -> Result<()> {
+    let state = generate_state_reporting(config).await?;
+    save_state(
+        &state,
+        output_path(&config.state_file_path, "state file path")?,
     )
-    .await;
+}
+
+/// Run IPS over the previously written state file.
+async fn ips(config: &CrunchyConfiguration) -> Result<()> {
+    let state_file_path = output_path(&config.state_file_path, "state file path")?;
+    let mut state = load_state(&state_file_path.display().to_string())?;
+    write_peers(config, &mut state).await
+}
+
+/// Print statistics of the previously written state file.
+fn stats(config: &CrunchyConfiguration) -> Result<()> {
+    let state_file_path = output_path(&config.state_file_path, "state file path")?;
+    let state = load_state(&state_file_path.display().to_string())?;
+    let mut output = Box::new(io::stdout()) as Box<dyn Write>;
+    print_state_statistics(&state, config, &mut output)
+}
+
+/// Simulate node removal over the previously written state file or the configured peer lists.
+fn percolate(config: &CrunchyConfiguration) -> Result<()> {
+    let state_file_path = output_path(&config.state_file_path, "state file path")?;
+    let output_file_path = output_path(
+        &config.percolation_config.output_file_path,
+        "percolation output file path",
+    )?;
+    let state = load_state(&state_file_path.display().to_string())?;
+    let curves = run_percolation(&state, config)?;
+
+    // Removed share at which the largest component drops below half of the network.
+    for curve in &curves {
+        match curve
+            .steps
+            .iter()
+            .find(|step| step.largest_component_share < 0.5)
+        {
+            Some(step) => println!(
+                "{} {:?}: network falls apart after removing {:.3}% of nodes",
+                curve.label,
+                curve.strategy,
+                step.removed_share * 100.0
+            ),
+            None => println!(
+                "{} {:?}: largest component keeps at least half of the nodes after removing {:.3}% of nodes",
+                curve.label,
+                curve.strategy,
+                curve.steps.last().map_or(0.0, |step| step.removed_share) * 100.0
+            ),
+        }
+    }
 
-    let histograms = create_histograms(&nodes).await;
+    save_curves(&curves, output_file_path)
+}
 
-    let state = CrunchyState {
-        elapsed: elapsed.as_secs_f64(),
-        nodes,
-        histograms,
+/// Run several IPS rounds with partial adoption over the previously written state file.
+async fn simulate(config: &CrunchyConfiguration) -> Result<()> {
+    // Rounds may be overridden from the command line.
+    config.ips_config.simulation.validate()?;
+    let state_file_path = output_path(&config.state_file_path, "state file path")?;
+    let state = load_state(&state_file_path.display().to_string())?;
+
+    let networks = if config.ips_config.all_networks {
+        networks_in_sample(&state.nodes)
+    } else {
+        vec![config.ips_config.network()]
     };
 
-    // Save all changes done to the cache
-    if let Err(res) = geo_cache.save().await {
-        println!("Could not save cache file: {}", res);
+    for network in networks {
+        let ips_config = if config.ips_config.all_networks {
+            config.ips_config.for_network(network)
+        } else {
+            config.ips_config.clone()
+        };
+
+        let simulation_file_path =
+            output_path(&ips_config.simulation_file_path, "simulation file path")?;
+        let rounds = simulate_rounds(
+            &state,
+            &ips_config,
+            &config.analysis_config,
+            network,
+            config.num_threads,
+        )
+        .await?;
+
+        for round in &rounds[1..] {
+            println!(
+                "{network:?} round {}: {} nodes adopted new peer lists, churn {:.2}%",
+                round.round,
+                round.adopted,
+                round.churn.churn_rate * 100.0
+            );
+        }
+        save_rounds(&rounds, simulation_file_path)?;
+        println!(
+            "{network:?}: simulation written to {}",
+            simulation_file_path.display()
+        );
     }
 
-    let mut ips = Ips::new(config.ips_config.clone());
-    let ips_peers = ips
-        .generate(&state, NetworkType::Zcash, config.num_threads)
-        .await;
+    Ok(())
+}
+
+/// Manage the geolocation cache.
+async fn geoip(config: &CrunchyConfiguration, action: &GeoIpAction) -> Result<()> {
+    let mut geo_cache = GeoIPCache::new(&config.geoip_config);
+    if !geo_cache.load().await? {
+        println!("No cache file to load, a new one will be created.");
+    }
 
-    let peerlist = serde_json::to_string(&ips_peers).unwrap();
-    fs::write(config.ips_config.peer_file_path.as_ref().unwrap(), peerlist).unwrap();
+    match action {
+        GeoIpAction::Info => {
+            println!(
+                "Cache file {} contains {} entries ({} expired)",
+                config.geoip_config.geocache_file_path.display(),
+                geo_cache.entries_count().await,
+                geo_cache.expired_count().await
+            );
+            return Ok(());
+        }
+        GeoIpAction::Prune => {
+            let removed = geo_cache.prune().await;
+            println!("Removed {removed} expired entries");
+        }
+        GeoIpAction::Update => {
+            let input_file_path = output_path(&config.input_file_path, "input file path")?;
+            let response = load_response(&input_file_path.display().to_string())?;
+            geo_cache.configure_providers(&config.geoip_config)?;
+
+            let mut located = 0;
+            for addr in &response.result.node_addrs {
+                if geo_cache.lookup(addr.ip()).await.is_some() {
+                    located += 1;
+                }
+            }
+            println!(
+                "Located {located} of {} nodes",
+                response.result.node_addrs.len()
+            );
+        }
+    }
 
-    let joutput = serde_json::to_string(&state).unwrap();
-    fs::write(config.state_file_path.as_ref().unwrap(), joutput).unwrap();
+    geo_cache.save().await
 }
 
-#[tokio::main]
-async fn main() {
-    let arg_conf = ArgConfiguration::parse();
-    let mut configuration = arg_conf
-        .config_file
-        .map(|path| {
-            CrunchyConfiguration::new(path.to_str().unwrap())
-                .expect("could not load configuration file")
-        })
-        .unwrap_or_default();
+/// Compare two peer list files.
+fn diff(old: &Path, new: &Path, output: Option<&Path>) -> Result<()> {
+    let old_peers = load_peers(&old.display().to_string())?;
+    let new_peers = load_peers(&new.display().to_string())?;
+    let diffs = PeerDiff::compare(&old_peers, &new_peers);
+
+    for diff in &diffs {
+        println!("{}: +{} -{}", diff.ip, diff.add.len(), diff.remove.len());
+    }
+    let churn = PeerChurn::new(&old_peers, &diffs);
+    println!(
+        "{} nodes changed, {} peers added, {} peers removed, churn {:.2}%",
+        churn.nodes_changed,
+        churn.added,
+        churn.removed,
+        churn.churn_rate * 100.0
+    );
+
+    if let Some(path) = output {
+        let joutput = serde_json::to_string(&diffs)
+            .map_err(|e| CrunchyError::parse("could not serialize peer diff", e))?;
+        fs::write(path, joutput).map_err(|e| {
+            CrunchyError::io(format!("could not write diff file {}", path.display()), e)
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Build configuration from the configuration file and command line overrides.
+fn load_configuration(arg_conf: ArgConfiguration) -> Result<CrunchyConfiguration> {
+    let mut configuration = match arg_conf.config_file {
+        Some(path) => CrunchyConfiguration::new(&path.display().to_string())?,
+        None => CrunchyConfiguration::default(),
+    };
 
     // Override configuration with command line arguments if provided
     if let Some(input_file) = arg_conf.input_sample {
@@ -118,153 +299,162 @@ async fn main() {
     if let Some(num_threads) = arg_conf.num_threads {
         configuration.num_threads = num_threads;
     }
+    if arg_conf.ips_network.is_some() {
+        configuration.ips_config.network = arg_conf.ips_network;
+    }
+    if arg_conf.all_networks {
+        configuration.ips_config.all_networks = true;
+    }
+
+    if configuration.ips_config.network == Some(NetworkType::Invalid) {
+        return Err(CrunchyError::Config(
+            "invalid network type for IPS, check Readme for possible values".to_owned(),
+        ));
+    }
 
     // Check if user error setting optional filter type
-    if arg_conf.filter_type.is_some() && arg_conf.filter_type.unwrap() == NetworkType::Invalid {
-        panic!("Invalid network type for filter. Check Readme for possible values.")
+    if arg_conf.filter_type == Some(NetworkType::Invalid) {
+        return Err(CrunchyError::Config(
+            "invalid network type for filter, check Readme for possible values".to_owned(),
+        ));
     }
 
     configuration.network_type_filter = arg_conf.filter_type;
 
-    if !configuration.input_file_path.as_ref().unwrap().is_file() {
-        eprintln!(
-            "{}: No such file or directory",
-            configuration
-                .input_file_path
-                .as_ref()
-                .unwrap()
-                .to_str()
-                .unwrap()
-        );
-        return;
+    Ok(configuration)
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let mut arg_conf = ArgConfiguration::parse();
+    let command = arg_conf.command.take();
+
+    let result = match load_configuration(arg_conf) {
+        Ok(configuration) => match command {
+            None => write_state(&configuration).await,
+            Some(Command::Crunch) => crunch(&configuration).await,
+            Some(Command::Ips) => ips(&configuration).await,
+            Some(Command::Stats) => stats(&configuration),
+            Some(Command::Geoip { action }) => geoip(&configuration, &action).await,
+            Some(Command::Diff { old, new, output }) => diff(&old, &new, output.as_deref()),
+            Some(Command::Percolate { peers, output }) => {
+                let mut configuration = configuration;
+                if !peers.is_empty() {
+                    configuration.percolation_config.peer_file_paths = peers;
+                }
+                if output.is_some() {
+                    configuration.percolation_config.output_file_path = output;
+                }
+                percolate(&configuration)
+            }
+            Some(Command::Simulate { rounds, output }) => {
+                let mut configuration = configuration;
+                if let Some(rounds) = rounds {
+                    configuration.ips_config.simulation.rounds = rounds;
+                }
+                if output.is_some() {
+                    configuration.ips_config.simulation_file_path = output;
+                }
+                simulate(&configuration).await
+            }
+        },
+        Err(err) => Err(err),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{}", err.report());
+            ExitCode::from(err.exit_code())
+        }
     }
-    write_state(&configuration).await;
 }
 
 #[derive(Parser, Debug)]
 #[clap(author = "Ziggurat Team", version, about, long_about = None)]
 pub struct ArgConfiguration {
+    /// Stage to run (if none, the whole pipeline is run: crunch and ips)
+    #[clap(subcommand)]
+    pub command: Option<Command>,
     /// Input file with sample data to process (overrides input from config file)
-    #[clap(short, long, value_parser)]
+    #[clap(short, long, value_parser, global = true)]
     pub input_sample: Option<PathBuf>,
-    /// Output file with state of the graph (overrides output from config file)
-    #[clap(short, long, value_parser)]
+    /// File with state of the graph - written by crunch, read by ips and stats (overrides state from config file)
+    #[clap(short, long, value_parser, global = true)]
     pub out_state: Option<PathBuf>,
     /// Output file with geolocation cache (overrides cache from config file)
-    #[clap(short, long, value_parser)]
+    #[clap(short, long, value_parser, global = true)]
     pub geocache_file: Option<PathBuf>,
     /// Configuration file path (if none defaults will be assumed)
-    #[clap(short, long, value_parser)]
+    #[clap(short, long, value_parser, global = true)]
     pub config_file: Option<PathBuf>,
     /// Intelligent Peer Sharing output file path (overrides ips from config file)
-    #[clap(short = 'p', long, value_parser)]
+    #[clap(short = 'p', long, value_parser, global = true)]
     pub ips_file: Option<PathBuf>,
     /// Number of threads to use for calculations (overrides number of threads from config file)
-    #[clap(short = 'j', long, value_parser)]
+    #[clap(short = 'j', long, value_parser, global = true)]
     pub num_threads: Option<usize>,
     /// Optional node filtering parameter; consult Readme for possible values
-    #[clap(short, long, value_parser)]
+    #[clap(short, long, value_parser, global = true)]
     pub filter_type: Option<NetworkType>,
+    /// Network to run Intelligent Peer Sharing on (overrides network from config file)
+    #[clap(short = 'n', long, value_parser, global = true)]
+    pub ips_network: Option<NetworkType>,
+    /// Run Intelligent Peer Sharing separately for every network found in the sample
+    #[clap(short, long, global = true)]
+    pub all_networks: bool,
 }
 
-#[cfg(test)]
-mod tests {
-
-    use std::{net::SocketAddr, thread};
-
-    use super::*;
-    use crate::config::GeoIPConfiguration;
-
-    #[tokio::test]
-    async fn create_nodes_unfiltered_test() {
-        let response = load_response("testdata/sample.json");
-
-        let config = GeoIPConfiguration::default();
-        let mut geo_cache = GeoIPCache::new(&config);
-        geo_cache.configure_providers(&config);
-
-        let num_threads = thread::available_parallelism().unwrap().get();
-        let nodes = create_nodes(
-            None,
-            &response.result.nodes_indices,
-            &response.result.node_addrs,
-            &response.result.node_network_types,
-            &geo_cache,
-            num_threads,
-        )
-        .await;
-
-        assert_eq!(nodes.len(), 6103);
-        assert_eq!(nodes[0].connections.len(), 2478);
-        assert_eq!(nodes[1].connections.len(), 2216);
-        assert_eq!(nodes[2].connections.len(), 1);
-        assert_eq!(nodes[3].connections.len(), 2184);
-        assert_eq!(nodes[3].connections[2], 609);
-    }
-
-    #[tokio::test]
-    async fn create_nodes_filtered_test1() {
-        let indices = vec![vec![1, 2], vec![0, 2, 3], vec![0, 1, 3], vec![1, 2]];
-        let node_addrs = vec![
-            SocketAddr::from(([127, 0, 0, 1], 1234)),
-            SocketAddr::from(([127, 0, 0, 2], 1234)),
-            SocketAddr::from(([127, 0, 0, 3], 1234)),
-            SocketAddr::from(([127, 0, 0, 4], 1234)),
-        ];
-        let node_network_types = vec![
-            NetworkType::Unknown,
-            NetworkType::Zcash,
-            NetworkType::Unknown,
-            NetworkType::Zcash,
-        ];
-        let config = GeoIPConfiguration::default();
-        let mut geo_cache = GeoIPCache::new(&config);
-        geo_cache.configure_providers(&config);
-
-        let num_threads = thread::available_parallelism().unwrap().get();
-        let nodes = create_nodes(
-            Some(NetworkType::Zcash),
-            &indices,
-            &node_addrs,
-            &node_network_types,
-            &geo_cache,
-            num_threads,
-        )
-        .await;
-        assert_eq!(nodes.len(), 2);
-        assert_eq!(nodes[0].connections, vec![1]);
-        assert_eq!(nodes[1].connections, vec![0]);
-    }
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Process the sample and write the state file only
+    Crunch,
+    /// Run Intelligent Peer Sharing over the existing state file
+    Ips,
+    /// Print network statistics of the existing state file
+    Stats,
+    /// Manage the geolocation cache
+    Geoip {
+        #[clap(subcommand)]
+        action: GeoIpAction,
+    },
+    /// Compare two peer list files
+    Diff {
+        /// Peer list file to compare against
+        old: PathBuf,
+        /// Peer list file to compare
+        new: PathBuf,
+        /// Optional file where per node differences are written as JSON
+        #[clap(long, value_parser)]
+        output: Option<PathBuf>,
+    },
+    /// Simulate node failures and attacks over the existing state file or peer lists
+    Percolate {
+        /// Peer list files to build networks from (overrides peer files from config file)
+        peers: Vec<PathBuf>,
+        /// File where percolation curves are written, CSV or JSON by extension (overrides
+        /// output from config file)
+        #[clap(long, value_parser)]
+        output: Option<PathBuf>,
+    },
+    /// Run several IPS rounds with partial adoption over the existing state file
+    Simulate {
+        /// Number of rounds (overrides rounds from config file)
+        #[clap(long, value_parser)]
+        rounds: Option<usize>,
+        /// File where statistics of every round are written as JSON (overrides simulation file
+        /// from config file)
+        #[clap(long, value_parser)]
+        output: Option<PathBuf>,
+    },
+}
 
-    #[tokio::test]
-    async fn create_nodes_filtered_test2() {
-        let response = load_response("testdata/sample.json");
-
-        let config = GeoIPConfiguration::default();
-        let mut geo_cache = GeoIPCache::new(&config);
-        geo_cache.configure_providers(&config);
-
-        let num_threads = thread::available_parallelism().unwrap().get();
-        let nodes = create_nodes(
-            Some(NetworkType::Zcash),
-            &response.result.nodes_indices,
-            &response.result.node_addrs,
-            &response.result.node_network_types,
-            &geo_cache,
-            num_threads,
-        )
-        .await;
-        assert_eq!(nodes.len(), 122);
-        assert_eq!(nodes[0].connections.len(), 2);
-        assert_eq!(nodes[1].connections.len(), 0);
-        assert_eq!(nodes[2].connections.len(), 1);
-        assert_eq!(nodes[3].connections.len(), 1);
-        assert_eq!(nodes[3].connections[0], 56);
-
-        let node = nodes[0].clone();
-        assert_eq!(node.addr.to_string(), "3.72.134.66:8233");
-        let epsilon: f64 = 0.0000001;
-        assert!((node.betweenness - 47.525898078529664).abs() < epsilon);
-        assert!((node.closeness - 1.603305785123967).abs() < epsilon);
-    }
+#[derive(Subcommand, Debug)]
+pub enum GeoIpAction {
+    /// Print number of cached and expired entries
+    Info,
+    /// Remove expired entries from the cache
+    Prune,
+    /// Geolocate all nodes from the input sample and store results in the cache
+    Update,
 }
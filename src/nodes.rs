@@ -1,14 +1,32 @@
-use std::net::SocketAddr;
+use std::{
+    net::{IpAddr, SocketAddr},
+    time::Instant,
+};
 
 use serde::{Deserialize, Serialize};
 use spectre::{edge::Edge, graph::Graph};
 use ziggurat_core_crawler::summary::{NetworkType, NodesIndices};
 use ziggurat_core_geoip::geoip::GeoInfo;
 
-use crate::{geoip_cache::GeoIPCache, histogram::Histogram};
+use crate::{
+    centrality::{approximate_centralities, pagerank, CentralityApproximation},
+    clustering::{compute_clustering, ClusteringSummary},
+    community::{detect_communities, CommunitySummary},
+    config::{AnalysisConfiguration, CommunityAlgorithm},
+    distance::{compute_distances, DistanceSummary},
+    eclipse::{compute_eclipse_risk, EclipseSummary},
+    geoip_cache::GeoIPCache,
+    histogram::Histogram,
+    ips::graph_utils::{adjacency_from_indices, adjacency_list, construct_graph},
+    kcore::{core_numbers, CoreProfile},
+    timings::Timings,
+};
 
 const HISTOGRAM_COUNTS: usize = 256;
 
+/// Number of bridging candidates proposed for each island.
+const BRIDGING_CANDIDATES_COUNT: usize = 3;
+
 #[derive(Default, Clone, Serialize, Deserialize)]
 pub struct HistogramSummary {
     /// Name of the histogram
@@ -19,6 +37,20 @@ pub struct HistogramSummary {
     pub max_count: usize,
 }
 
+/// Summary of a connected component (island) of the network
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ComponentSummary {
+    /// Identifier of the component - components are ordered by size, so 0 is the main one
+    pub id: usize,
+    /// Number of nodes in the component
+    pub size: usize,
+    /// Share of all nodes which belong to the component
+    pub share: f64,
+    /// Pairs of node indices (island node, main component node) that would join the island to
+    /// the main component if connected
+    pub bridging_candidates: Vec<(usize, usize)>,
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Node {
     /// the ip address with port number
@@ -29,6 +61,34 @@ pub struct Node {
     pub betweenness: f64,
     /// the computed closeness
     pub closeness: f64,
+    /// number of distinct connected nodes
+    #[serde(default)]
+    pub degree: usize,
+    /// the computed eigenvector centrality
+    #[serde(default)]
+    pub eigenvector: f64,
+    /// the computed PageRank
+    #[serde(default)]
+    pub pagerank: f64,
+    /// the computed local clustering coefficient
+    #[serde(default)]
+    pub clustering: f64,
+    /// the highest k for which the node belongs to the k-core
+    #[serde(default)]
+    pub core_number: usize,
+    /// the greatest number of hops to any reachable node
+    #[serde(default)]
+    pub eccentricity: usize,
+    /// identifier of the community the node belongs to (0 is the biggest one)
+    #[serde(default)]
+    pub community: usize,
+    /// identifier of the connected component the node belongs to (0 is the biggest one)
+    #[serde(default)]
+    pub component_id: usize,
+    /// how easily the node could be isolated by an attacker (0 - safe, 1 - risky), none if it
+    /// wasn't computed
+    #[serde(default)]
+    pub eclipse_risk: Option<f64>,
     /// indices of all connected nodes
     pub connections: Vec<usize>,
     /// used for latitude, longitude, city, country
@@ -43,6 +103,15 @@ impl Default for Node {
             network_type: NetworkType::Unknown,
             betweenness: 0.0,
             closeness: 0.0,
+            degree: 0,
+            eigenvector: 0.0,
+            pagerank: 0.0,
+            clustering: 0.0,
+            core_number: 0,
+            eccentricity: 0,
+            community: 0,
+            component_id: 0,
+            eclipse_risk: None,
             connections: Vec::new(),
             geolocation: None,
         }
@@ -54,7 +123,9 @@ pub async fn create_nodes_unfiltered(
     node_addrs: &[SocketAddr],
     node_network_types: &[NetworkType],
     geo_cache: &GeoIPCache,
+    analysis_config: &AnalysisConfiguration,
     num_threads: usize,
+    timings: &mut Timings,
 ) -> Vec<Node> {
     let mut graph = Graph::new();
     for (n, node) in indices.iter().enumerate() {
@@ -65,35 +136,39 @@ pub async fn create_nodes_unfiltered(
             });
     }
 
-    let betweenness = graph.betweenness_centrality(num_threads, false);
-    let closeness = graph.closeness_centrality(num_threads);
+    let (betweenness, closeness) =
+        compute_centralities(&mut graph, indices, analysis_config, num_threads, timings);
     let mut nodes = Vec::with_capacity(indices.len());
 
     for i in 0..indices.len() {
+        let lookup_start = Instant::now();
+        let geolocation = geo_cache.lookup(node_addrs[i].ip()).await;
+        timings.accumulate("geolocation", lookup_start.elapsed());
+
         let node: Node = Node {
             addr: node_addrs[i],
             network_type: node_network_types[i],
-            betweenness: *betweenness
-                .get(&i)
-                .expect("could not find betweenness value for index}"),
-            closeness: *closeness
-                .get(&i)
-                .expect("could not find closeness value for index"),
+            betweenness: betweenness[i],
+            closeness: closeness[i],
             connections: indices[i].clone(),
-            geolocation: geo_cache.lookup(node_addrs[i].ip()).await,
+            geolocation,
+            ..Default::default()
         };
         nodes.push(node);
     }
     nodes
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn create_nodes_filtered(
     network_type_filter: NetworkType,
     indices: &NodesIndices,
     node_addrs: &[SocketAddr],
     node_network_types: &[NetworkType],
     geo_cache: &GeoIPCache,
+    analysis_config: &AnalysisConfiguration,
     num_threads: usize,
+    timings: &mut Timings,
 ) -> Vec<Node> {
     let num_nodes = indices.len();
 
@@ -143,25 +218,31 @@ pub async fn create_nodes_filtered(
         }
     }
 
-    let betweenness = graph.betweenness_centrality(num_threads, false);
-    let closeness = graph.closeness_centrality(num_threads);
+    let (betweenness, closeness) = compute_centralities(
+        &mut graph,
+        &new_indices,
+        analysis_config,
+        num_threads,
+        timings,
+    );
     let mut nodes = Vec::with_capacity(indices.len());
 
     // here we use the original indexing, because of the node addrs array
     for i in 0..indices.len() {
         let index = index_map[i];
         if index != -1 {
+            let lookup_start = Instant::now();
+            let geolocation = geo_cache.lookup(node_addrs[i].ip()).await;
+            timings.accumulate("geolocation", lookup_start.elapsed());
+
             let node: Node = Node {
                 addr: node_addrs[i],
                 network_type: node_network_types[i],
-                betweenness: *betweenness
-                    .get(&(index as usize))
-                    .expect("could not find betweenness value for index}"),
-                closeness: *closeness
-                    .get(&(index as usize))
-                    .expect("could not find closeness value for index"),
+                betweenness: betweenness[index as usize],
+                closeness: closeness[index as usize],
                 connections: new_indices[index as usize].clone(),
-                geolocation: geo_cache.lookup(node_addrs[i].ip()).await,
+                geolocation,
+                ..Default::default()
             };
             nodes.push(node);
         }
@@ -169,15 +250,18 @@ pub async fn create_nodes_filtered(
     nodes
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn create_nodes(
     filter_type: Option<NetworkType>,
     indices: &NodesIndices,
     node_addrs: &[SocketAddr],
     node_network_types: &[NetworkType],
     geo_cache: &GeoIPCache,
+    analysis_config: &AnalysisConfiguration,
     num_threads: usize,
+    timings: &mut Timings,
 ) -> Vec<Node> {
-    match filter_type {
+    let mut nodes = match filter_type {
         Some(network_type) => {
             create_nodes_filtered(
                 network_type,
@@ -185,7 +269,9 @@ pub async fn create_nodes(
                 node_addrs,
                 node_network_types,
                 geo_cache,
+                analysis_config,
                 num_threads,
+                timings,
             )
             .await
         }
@@ -195,56 +281,449 @@ pub async fn create_nodes(
                 node_addrs,
                 node_network_types,
                 geo_cache,
+                analysis_config,
                 num_threads,
+                timings,
             )
             .await
         }
+    };
+
+    timings.measure("node_metrics", || {
+        compute_node_metrics(&mut nodes, analysis_config)
+    });
+    nodes
+}
+
+/// Compute degree, eigenvector centrality and PageRank of the nodes.
+pub fn compute_node_metrics(nodes: &mut [Node], analysis_config: &AnalysisConfiguration) {
+    let adjacency = adjacency_list(nodes);
+    let pagerank = pagerank(&adjacency, analysis_config.pagerank_damping);
+    let eigenvector = construct_graph(nodes).eigenvalue_centrality();
+
+    for (idx, node) in nodes.iter_mut().enumerate() {
+        node.degree = adjacency[idx].len();
+        node.pagerank = pagerank[idx];
+        node.eigenvector = eigenvector.get(&node.addr).copied().unwrap_or_default();
     }
 }
 
-pub async fn create_histograms(nodes: &[Node]) -> Vec<HistogramSummary> {
-    // Betweenness
-    let mut histogram_b = Histogram {
-        ..Histogram::default()
-    };
+/// Compute betweenness and closeness of the graph nodes (graph uses node indices as vertices).
+/// Depending on the configuration values are either exact or approximated.
+fn compute_centralities(
+    graph: &mut Graph<usize>,
+    indices: &[Vec<usize>],
+    analysis_config: &AnalysisConfiguration,
+    num_threads: usize,
+    timings: &mut Timings,
+) -> (Vec<f64>, Vec<f64>) {
+    if let Some(approximation) =
+        CentralityApproximation::from_config(analysis_config, indices.len())
+    {
+        let adjacency = adjacency_from_indices(indices);
+        return timings.measure("approximate_centralities", || {
+            approximate_centralities(&adjacency, &approximation, num_threads)
+        });
+    }
 
-    // Closeness
-    let mut histogram_c = Histogram {
-        ..Histogram::default()
+    let betweenness = timings.measure("betweenness", || {
+        graph.betweenness_centrality(num_threads, false)
+    });
+    let closeness = timings.measure("closeness", || graph.closeness_centrality(num_threads));
+
+    // Isolated nodes are not in the graph, they have no centrality.
+    (0..indices.len())
+        .map(|i| {
+            (
+                betweenness.get(&i).copied().unwrap_or_default(),
+                closeness.get(&i).copied().unwrap_or_default(),
+            )
+        })
+        .unzip()
+}
+
+/// Assign local clustering coefficients to the nodes and compute network wide clustering figures.
+pub fn analyze_clustering(nodes: &mut [Node]) -> ClusteringSummary {
+    let (coefficients, summary) = compute_clustering(&adjacency_list(nodes));
+    for (node, coefficient) in nodes.iter_mut().zip(coefficients) {
+        node.clustering = coefficient;
+    }
+    summary
+}
+
+/// Assign core numbers to the nodes and build the core-size profile of the network.
+pub fn analyze_cores(nodes: &mut [Node], core_threshold: Option<usize>) -> CoreProfile {
+    let core_numbers = core_numbers(&adjacency_list(nodes));
+    for (node, core_number) in nodes.iter_mut().zip(&core_numbers) {
+        node.core_number = *core_number;
+    }
+    CoreProfile::new(&core_numbers, core_threshold)
+}
+
+/// Assign eccentricities to the nodes and compute network wide distance figures. If sample size
+/// is given, distances are measured only from that many randomly chosen nodes.
+pub fn analyze_distances(
+    nodes: &mut [Node],
+    sample_size: Option<usize>,
+    seed: u64,
+    num_threads: usize,
+) -> DistanceSummary {
+    let (eccentricities, summary) =
+        compute_distances(&adjacency_list(nodes), sample_size, seed, num_threads);
+    for (node, eccentricity) in nodes.iter_mut().zip(eccentricities) {
+        node.eccentricity = eccentricity;
+    }
+    summary
+}
+
+/// Assign community ids to the nodes and compute modularity and sizes of the communities.
+pub fn analyze_communities(
+    nodes: &mut [Node],
+    algorithm: CommunityAlgorithm,
+    seed: u64,
+) -> CommunitySummary {
+    let (communities, summary) = detect_communities(&adjacency_list(nodes), algorithm, seed);
+    for (node, community) in nodes.iter_mut().zip(communities) {
+        node.community = community;
+    }
+    summary
+}
+
+/// Assign eclipse risk to the nodes and compute network wide eclipse risk figures. Core numbers
+/// must be already assigned.
+pub fn analyze_eclipse_risk(nodes: &mut [Node], num_threads: usize) -> EclipseSummary {
+    let core_numbers = nodes.iter().map(|n| n.core_number).collect::<Vec<_>>();
+    let (factors, summary) =
+        compute_eclipse_risk(nodes, &adjacency_list(nodes), &core_numbers, num_threads);
+    for (node, factors) in nodes.iter_mut().zip(factors) {
+        node.eclipse_risk = Some(factors.risk());
+    }
+    summary
+}
+
+/// Find connected components of the network. Components are sorted by size (biggest first) and
+/// contain sorted node indices.
+pub fn detect_components(nodes: &[Node]) -> Vec<Vec<usize>> {
+    let adjacency = adjacency_list(nodes);
+    let mut visited = vec![false; nodes.len()];
+    let mut components = Vec::new();
+
+    for start in 0..nodes.len() {
+        if visited[start] {
+            continue;
+        }
+
+        visited[start] = true;
+        let mut component = vec![start];
+        let mut stack = vec![start];
+        while let Some(node_idx) = stack.pop() {
+            for &peer_idx in &adjacency[node_idx] {
+                if !visited[peer_idx] {
+                    visited[peer_idx] = true;
+                    component.push(peer_idx);
+                    stack.push(peer_idx);
+                }
+            }
+        }
+
+        component.sort_unstable();
+        components.push(component);
+    }
+
+    // Stable sort keeps components of the same size ordered by their first node.
+    components.sort_by_key(|component| std::cmp::Reverse(component.len()));
+    components
+}
+
+/// Assign component identifiers to the nodes and summarize components.
+/// Bridging candidates connect the most central nodes of the island (highest degree, then highest
+/// betweenness) with the main component nodes with lowest betweenness - so new connections don't create new hot
+/// nodes. Several disjoint pairs are proposed, as a single connection would be a bridge itself.
+pub fn analyze_components(nodes: &mut [Node]) -> Vec<ComponentSummary> {
+    let components = detect_components(nodes);
+
+    for (id, component) in components.iter().enumerate() {
+        for node_idx in component {
+            nodes[*node_idx].component_id = id;
+        }
+    }
+
+    let mut main_candidates = components.first().cloned().unwrap_or_default();
+    main_candidates.sort_by(|a, b| nodes[*a].betweenness.total_cmp(&nodes[*b].betweenness));
+    main_candidates.truncate(BRIDGING_CANDIDATES_COUNT);
+
+    components
+        .iter()
+        .enumerate()
+        .map(|(id, component)| {
+            let bridging_candidates = if id == 0 {
+                Vec::new()
+            } else {
+                let mut island_candidates = component.clone();
+                island_candidates.sort_by(|a, b| {
+                    nodes[*b]
+                        .degree
+                        .cmp(&nodes[*a].degree)
+                        .then(nodes[*b].betweenness.total_cmp(&nodes[*a].betweenness))
+                        .then(a.cmp(b))
+                });
+                island_candidates
+                    .into_iter()
+                    .zip(main_candidates.iter().copied())
+                    .collect()
+            };
+
+            ComponentSummary {
+                id,
+                size: component.len(),
+                share: component.len() as f64 / nodes.len() as f64,
+                bridging_candidates,
+            }
+        })
+        .collect()
+}
+
+/// Subnet (netgroup) the address belongs to - /16 for IPv4 and /32 for IPv6. Peers from one
+/// subnet are likely run by a single operator.
+pub fn subnet(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => {
+            let octets = ip.octets();
+            format!("{}.{}.0.0/16", octets[0], octets[1])
+        }
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            format!("{:x}:{:x}::/32", segments[0], segments[1])
+        }
+    }
+}
+
+/// Compute content hash of the graph (node addresses and their connections). Order of the
+/// connections doesn't matter. FNV-1a is used as it's stable across builds and platforms, so
+/// hashes stored in state files can be compared later.
+pub fn graph_hash(nodes: &[Node]) -> String {
+    const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const FNV_PRIME: u64 = 0x100000001b3;
+
+    let mut hash = FNV_OFFSET_BASIS;
+    let mut feed = |bytes: &[u8]| {
+        for byte in bytes {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(FNV_PRIME);
+        }
     };
 
-    // Degree
-    let mut histogram_d = Histogram {
+    for node in nodes {
+        feed(node.addr.to_string().as_bytes());
+
+        let mut connections = node.connections.clone();
+        connections.sort_unstable();
+        feed(&(connections.len() as u64).to_le_bytes());
+        for connection in connections {
+            feed(&(connection as u64).to_le_bytes());
+        }
+    }
+
+    format!("{hash:016x}")
+}
+
+pub async fn create_histograms(nodes: &[Node]) -> Vec<HistogramSummary> {
+    vec![
+        histogram_summary("betweenness", nodes.iter().map(|n| n.betweenness)),
+        histogram_summary("closeness", nodes.iter().map(|n| n.closeness)),
+        histogram_summary("degree", nodes.iter().map(|n| n.degree as f64)),
+        histogram_summary("eigenvector", nodes.iter().map(|n| n.eigenvector)),
+        histogram_summary("pagerank", nodes.iter().map(|n| n.pagerank)),
+        histogram_summary("clustering", nodes.iter().map(|n| n.clustering)),
+        histogram_summary("core_number", nodes.iter().map(|n| n.core_number as f64)),
+        histogram_summary("eccentricity", nodes.iter().map(|n| n.eccentricity as f64)),
+        histogram_summary("eclipse_risk", nodes.iter().filter_map(|n| n.eclipse_risk)),
+    ]
+}
+
+/// Compute histogram of the values for the renderer.
+fn histogram_summary(label: &str, values: impl Iterator<Item = f64>) -> HistogramSummary {
+    let mut histogram = Histogram {
         ..Histogram::default()
     };
 
-    for node in nodes.iter() {
-        histogram_b.add(node.betweenness);
-        histogram_c.add(node.closeness);
-        histogram_d.add(node.connections.len() as f64);
+    for value in values {
+        histogram.add(value);
     }
 
-    let mut histograms = Vec::new();
-    let (counts, max_count) = histogram_b.compute(HISTOGRAM_COUNTS);
-    histograms.push(HistogramSummary {
-        label: "betweenness".to_owned(),
+    let (counts, max_count) = histogram.compute(HISTOGRAM_COUNTS);
+    HistogramSummary {
+        label: label.to_owned(),
         counts,
         max_count,
-    });
+    }
+}
 
-    let (counts, max_count) = histogram_c.compute(HISTOGRAM_COUNTS);
-    histograms.push(HistogramSummary {
-        label: "closeness".to_owned(),
-        counts,
-        max_count,
-    });
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
 
-    let (counts, max_count) = histogram_d.compute(HISTOGRAM_COUNTS);
-    histograms.push(HistogramSummary {
-        label: "degree".to_owned(),
-        counts,
-        max_count,
-    });
+    use super::*;
+
+    #[test]
+    fn graph_hash_test() {
+        let nodes = vec![
+            Node {
+                addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(1, 0, 0, 0)), 1234),
+                connections: vec![1, 2],
+                ..Default::default()
+            },
+            Node {
+                addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(2, 0, 0, 0)), 1234),
+                connections: vec![0],
+                ..Default::default()
+            },
+            Node {
+                addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(3, 0, 0, 0)), 1234),
+                connections: vec![0],
+                ..Default::default()
+            },
+        ];
 
-    histograms
+        let hash = graph_hash(&nodes);
+        assert_eq!(hash.len(), 16);
+
+        // Connections order and metrics don't matter
+        let mut reordered = nodes.clone();
+        reordered[0].connections = vec![2, 1];
+        reordered[0].betweenness = 1.0;
+        assert_eq!(graph_hash(&reordered), hash);
+
+        // Topology does
+        let mut changed = nodes.clone();
+        changed[1].connections.push(2);
+        changed[2].connections.push(1);
+        assert_ne!(graph_hash(&changed), hash);
+    }
+
+    #[test]
+    fn compute_centralities_isolated_test() {
+        // Path 0 - 1 - 2 and isolated node 3, which is not in the graph.
+        let indices = vec![vec![1], vec![0, 2], vec![1], vec![]];
+        let mut graph = Graph::new();
+        graph.insert(Edge::new(0, 1));
+        graph.insert(Edge::new(1, 2));
+
+        let (betweenness, closeness) = compute_centralities(
+            &mut graph,
+            &indices,
+            &AnalysisConfiguration::default(),
+            1,
+            &mut Timings::default(),
+        );
+        assert_eq!(betweenness.len(), 4);
+        assert!(betweenness[1] > betweenness[0]);
+        assert_eq!(betweenness[3], 0.0);
+        assert_eq!(closeness[3], 0.0);
+    }
+
+    #[test]
+    fn subnet_test() {
+        assert_eq!(subnet("10.0.1.2".parse().unwrap()), "10.0.0.0/16");
+        assert_eq!(subnet("2001:db8::1".parse().unwrap()), "2001:db8::/32");
+    }
+
+    #[test]
+    fn analyze_components_test() {
+        // Main component 0 - 1 - 2 - 3, island 4 - 5 - 6 and isolated node 7.
+        let connections = vec![
+            vec![1],
+            vec![0, 2],
+            vec![1, 3],
+            vec![2],
+            vec![5],
+            vec![4, 6],
+            vec![5],
+            vec![],
+        ];
+        let betweenness = [0.0, 2.0, 2.0, 0.0, 0.0, 1.0, 0.0, 0.0];
+        // Leaf 4 has the lowest closeness, it mustn't be taken for the island center.
+        let closeness = [2.0, 1.3, 1.3, 2.0, 0.0, 1.0, 1.5, 0.0];
+        let degree = connections.iter().map(Vec::len).collect::<Vec<_>>();
+        let mut nodes = connections
+            .into_iter()
+            .enumerate()
+            .map(|(i, connections)| Node {
+                addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(i as u8, 0, 0, 0)), 1234),
+                connections,
+                betweenness: betweenness[i],
+                closeness: closeness[i],
+                degree: degree[i],
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+
+        let components = analyze_components(&mut nodes);
+        assert_eq!(components.len(), 3);
+        assert_eq!(
+            nodes.iter().map(|n| n.component_id).collect::<Vec<_>>(),
+            vec![0, 0, 0, 0, 1, 1, 1, 2]
+        );
+
+        assert_eq!(components[0].size, 4);
+        assert!((components[0].share - 4.0 / 8.0).abs() < f64::EPSILON);
+        assert!(components[0].bridging_candidates.is_empty());
+
+        assert_eq!(
+            components[1].bridging_candidates,
+            vec![(5, 0), (4, 3), (6, 1)]
+        );
+        assert_eq!(components[2].bridging_candidates, vec![(7, 0)]);
+    }
+
+    #[tokio::test]
+    async fn compute_node_metrics_test() {
+        // Path 0 - 1 - 2 with a duplicated connection.
+        let mut nodes = vec![
+            Node {
+                addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(1, 0, 0, 0)), 1234),
+                connections: vec![1, 1],
+                ..Default::default()
+            },
+            Node {
+                addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(2, 0, 0, 0)), 1234),
+                connections: vec![0, 2],
+                ..Default::default()
+            },
+            Node {
+                addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(3, 0, 0, 0)), 1234),
+                connections: vec![1],
+                ..Default::default()
+            },
+        ];
+
+        compute_node_metrics(&mut nodes, &AnalysisConfiguration::default());
+
+        assert_eq!(
+            nodes.iter().map(|n| n.degree).collect::<Vec<_>>(),
+            vec![1, 2, 1]
+        );
+        assert!(nodes[1].pagerank > nodes[0].pagerank);
+        assert!((nodes[0].pagerank - nodes[2].pagerank).abs() < 1e-12);
+        assert!(nodes[1].eigenvector > nodes[0].eigenvector);
+
+        let histograms = create_histograms(&nodes).await;
+        assert_eq!(
+            histograms
+                .iter()
+                .map(|h| h.label.as_str())
+                .collect::<Vec<_>>(),
+            vec![
+                "betweenness",
+                "closeness",
+                "degree",
+                "eigenvector",
+                "pagerank",
+                "clustering",
+                "core_number",
+                "eccentricity",
+                "eclipse_risk"
+            ]
+        );
+    }
 }
@@ -0,0 +1,446 @@
+//! Attack and failure percolation simulator.
+//!
+//! Nodes are removed from the network step by step - at random or in order of their importance -
+//! and the size of the largest component and the number of islands are recorded after each step.
+//! The resulting curve shows how much damage the network can take before it falls apart, so
+//! networks built from different peer lists (e.g. vanilla and IPS ones) can be compared.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fs,
+    path::Path,
+};
+
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    centrality::{approximate_centralities, CentralityApproximation},
+    config::{AnalysisConfiguration, PercolationConfiguration},
+    error::{CrunchyError, Result},
+    ips::{graph_utils::adjacency_list, peer::Peer},
+    nodes::subnet,
+    Node,
+};
+
+/// RemovalStrategy enum - indicates which nodes are removed in each step.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub enum RemovalStrategy {
+    /// Random nodes - simulates failures
+    Random,
+    /// Nodes with the highest degree in the original network
+    HighestDegree,
+    /// Nodes with the highest betweenness, recomputed after each step
+    HighestBetweenness,
+    /// All nodes located in one country, biggest country first
+    Country,
+    /// All nodes from one subnet (IPv4 /16, IPv6 /32), biggest subnet first
+    Subnet,
+}
+
+/// State of the network after a single removal step.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PercolationStep {
+    /// Number of removed nodes
+    pub removed: usize,
+    /// Share of the original nodes which were removed
+    pub removed_share: f64,
+    /// Number of nodes in the largest component
+    pub largest_component: usize,
+    /// Share of the original nodes which are in the largest component
+    pub largest_component_share: f64,
+    /// Number of connected components formed by the remaining nodes
+    pub islands: usize,
+}
+
+/// Percolation curve of a single network and removal strategy.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PercolationCurve {
+    /// Name of the network the curve was computed for (e.g. peer file name)
+    pub label: String,
+    /// Strategy used to choose removed nodes
+    pub strategy: RemovalStrategy,
+    /// Number of nodes in the original network
+    pub nodes: usize,
+    /// Network state before any removal followed by the state after each step
+    pub steps: Vec<PercolationStep>,
+}
+
+/// Simulate removal of the nodes with given strategy. Nodes are removed until the configured
+/// share of them is gone or there is nothing more to remove.
+pub fn simulate(
+    label: &str,
+    nodes: &[Node],
+    strategy: RemovalStrategy,
+    config: &PercolationConfiguration,
+    analysis_config: &AnalysisConfiguration,
+    num_threads: usize,
+) -> PercolationCurve {
+    let adjacency = adjacency_list(nodes);
+    let total = nodes.len();
+    let max_removed = (total as f64 * config.max_removed_percentage).round() as usize;
+    let step_size = ((total as f64 * config.step_percentage).ceil() as usize).max(1);
+
+    let mut removed = vec![false; total];
+    let mut removed_count = 0;
+    let mut steps = vec![measure(&adjacency, &removed, removed_count)];
+
+    // Removal order is known upfront for all strategies but the adaptive one.
+    let mut order = match strategy {
+        RemovalStrategy::Random => {
+            let mut order = (0..total).map(|idx| vec![idx]).collect::<Vec<_>>();
+            order.shuffle(&mut StdRng::seed_from_u64(analysis_config.seed));
+            order
+        }
+        RemovalStrategy::HighestDegree => {
+            let mut order = (0..total).collect::<Vec<_>>();
+            order.sort_by_key(|idx| std::cmp::Reverse(adjacency[*idx].len()));
+            order.into_iter().map(|idx| vec![idx]).collect()
+        }
+        RemovalStrategy::HighestBetweenness => Vec::new(),
+        RemovalStrategy::Country => group_nodes(nodes, |node| {
+            node.geolocation
+                .as_ref()
+                .and_then(|geolocation| geolocation.country.clone())
+        }),
+        RemovalStrategy::Subnet => group_nodes(nodes, |node| Some(subnet(node.addr.ip()))),
+    }
+    .into_iter();
+
+    while removed_count < max_removed && removed_count < total {
+        // Single node strategies remove step_size nodes at once, group strategies whole groups -
+        // as long as the group fits into the limit.
+        let batch = match strategy {
+            RemovalStrategy::HighestBetweenness => highest_betweenness(
+                &adjacency,
+                &removed,
+                step_size.min(max_removed - removed_count),
+                analysis_config,
+                num_threads,
+            ),
+            RemovalStrategy::Country | RemovalStrategy::Subnet => match order.next() {
+                Some(group) if removed_count + group.len() <= max_removed => group,
+                _ => break,
+            },
+            RemovalStrategy::Random | RemovalStrategy::HighestDegree => order
+                .by_ref()
+                .take(step_size.min(max_removed - removed_count))
+                .flatten()
+                .collect(),
+        };
+
+        if batch.is_empty() {
+            break;
+        }
+        for idx in batch {
+            removed[idx] = true;
+            removed_count += 1;
+        }
+        steps.push(measure(&adjacency, &removed, removed_count));
+    }
+
+    PercolationCurve {
+        label: label.to_owned(),
+        strategy,
+        nodes: total,
+        steps,
+    }
+}
+
+/// Build nodes from the peer lists. Network type and geolocation are copied from the known nodes
+/// with the same address.
+pub fn nodes_from_peers(peers: &[Peer], known_nodes: &[Node]) -> Vec<Node> {
+    let known = known_nodes
+        .iter()
+        .map(|node| (node.addr, node))
+        .collect::<HashMap<_, _>>();
+    let indices = peers
+        .iter()
+        .enumerate()
+        .map(|(idx, peer)| (peer.ip, idx))
+        .collect::<HashMap<_, _>>();
+
+    peers
+        .iter()
+        .map(|peer| {
+            let mut node = Node {
+                addr: peer.ip,
+                connections: peer
+                    .list
+                    .iter()
+                    .filter_map(|addr| indices.get(addr).copied())
+                    .collect(),
+                ..Default::default()
+            };
+            if let Some(known_node) = known.get(&peer.ip) {
+                node.network_type = known_node.network_type;
+                node.geolocation = known_node.geolocation.clone();
+            }
+            node
+        })
+        .collect()
+}
+
+/// Write percolation curves to the file - as CSV if the file has `csv` extension, as JSON
+/// otherwise.
+pub fn save_curves(curves: &[PercolationCurve], path: &Path) -> Result<()> {
+    let content = if path.extension().is_some_and(|ext| ext == "csv") {
+        let mut csv = String::from(
+            "label,strategy,removed,removed_share,largest_component,largest_component_share,islands\n",
+        );
+        for curve in curves {
+            for step in &curve.steps {
+                csv.push_str(&format!(
+                    "{},{:?},{},{},{},{},{}\n",
+                    csv_field(&curve.label),
+                    curve.strategy,
+                    step.removed,
+                    step.removed_share,
+                    step.largest_component,
+                    step.largest_component_share,
+                    step.islands
+                ));
+            }
+        }
+        csv
+    } else {
+        serde_json::to_string(curves)
+            .map_err(|e| CrunchyError::parse("could not serialize percolation curves", e))?
+    };
+
+    fs::write(path, content).map_err(|e| {
+        CrunchyError::io(
+            format!("could not write percolation file {}", path.display()),
+            e,
+        )
+    })
+}
+
+/// Quote the CSV field if it contains a separator, quote or line break (quotes are doubled).
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
+/// Group node indices by the key, biggest group first. Nodes without the key are never removed.
+fn group_nodes(nodes: &[Node], key: impl Fn(&Node) -> Option<String>) -> Vec<Vec<usize>> {
+    let mut groups: HashMap<String, Vec<usize>> = HashMap::new();
+    for (idx, node) in nodes.iter().enumerate() {
+        if let Some(key) = key(node) {
+            groups.entry(key).or_default().push(idx);
+        }
+    }
+
+    let mut groups = groups.into_values().collect::<Vec<_>>();
+    groups.sort_by_key(|group| (std::cmp::Reverse(group.len()), group[0]));
+    groups
+}
+
+/// Find remaining nodes with the highest betweenness in the network without removed nodes.
+fn highest_betweenness(
+    adjacency: &[Vec<usize>],
+    removed: &[bool],
+    count: usize,
+    analysis_config: &AnalysisConfiguration,
+    num_threads: usize,
+) -> Vec<usize> {
+    let remaining = remaining_adjacency(adjacency, removed);
+
+    // With all nodes used as pivots the betweenness is exact.
+    let approximation = CentralityApproximation::from_config(analysis_config, adjacency.len())
+        .unwrap_or(CentralityApproximation {
+            pivots: adjacency.len(),
+            nodes: adjacency.len(),
+            error_bound: None,
+            seed: analysis_config.seed,
+        });
+    let (betweenness, _) = approximate_centralities(&remaining, &approximation, num_threads);
+
+    let mut candidates = (0..adjacency.len())
+        .filter(|idx| !removed[*idx])
+        .collect::<Vec<_>>();
+    candidates.sort_by(|a, b| betweenness[*b].total_cmp(&betweenness[*a]));
+    candidates.truncate(count);
+    candidates
+}
+
+/// Adjacency list with connections of the removed nodes dropped.
+fn remaining_adjacency(adjacency: &[Vec<usize>], removed: &[bool]) -> Vec<Vec<usize>> {
+    adjacency
+        .iter()
+        .enumerate()
+        .map(|(idx, peers)| {
+            if removed[idx] {
+                Vec::new()
+            } else {
+                peers
+                    .iter()
+                    .copied()
+                    .filter(|peer| !removed[*peer])
+                    .collect()
+            }
+        })
+        .collect()
+}
+
+/// Find the largest component and count components formed by the remaining nodes.
+fn measure(adjacency: &[Vec<usize>], removed: &[bool], removed_count: usize) -> PercolationStep {
+    let total = adjacency.len();
+    let mut visited = removed.to_vec();
+    let mut queue = VecDeque::new();
+    let mut largest_component = 0;
+    let mut islands = 0;
+
+    for start in 0..total {
+        if visited[start] {
+            continue;
+        }
+
+        visited[start] = true;
+        queue.push_back(start);
+        let mut size = 0;
+        while let Some(node) = queue.pop_front() {
+            size += 1;
+            for &peer in &adjacency[node] {
+                if !visited[peer] {
+                    visited[peer] = true;
+                    queue.push_back(peer);
+                }
+            }
+        }
+
+        islands += 1;
+        largest_component = largest_component.max(size);
+    }
+
+    let share = |count: usize| {
+        if total > 0 {
+            count as f64 / total as f64
+        } else {
+            0.0
+        }
+    };
+
+    PercolationStep {
+        removed: removed_count,
+        removed_share: share(removed_count),
+        largest_component,
+        largest_component_share: share(largest_component),
+        islands,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+    use super::*;
+
+    /// Star with center 0 and leaves 1-4 in subnet 10.0.0.0/16, center in 11.0.0.0/16.
+    fn star() -> Vec<Node> {
+        let connections = vec![vec![1, 2, 3, 4], vec![0], vec![0], vec![0], vec![0]];
+        connections
+            .into_iter()
+            .enumerate()
+            .map(|(i, connections)| Node {
+                addr: SocketAddr::new(
+                    IpAddr::V4(Ipv4Addr::new(10 + u8::from(i == 0), 0, 0, i as u8)),
+                    1234,
+                ),
+                connections,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn simulate_test() {
+        let nodes = star();
+        let config = PercolationConfiguration {
+            step_percentage: 0.2,
+            max_removed_percentage: 0.4,
+            ..Default::default()
+        };
+        let analysis_config = AnalysisConfiguration::default();
+
+        // Removing the center shatters the star.
+        for strategy in [
+            RemovalStrategy::HighestDegree,
+            RemovalStrategy::HighestBetweenness,
+        ] {
+            let curve = simulate("star", &nodes, strategy, &config, &analysis_config, 2);
+            assert_eq!(curve.steps.len(), 3);
+            assert_eq!(curve.steps[0].largest_component, 5);
+            assert_eq!(curve.steps[0].islands, 1);
+            assert_eq!(curve.steps[1].removed, 1);
+            assert_eq!(curve.steps[1].largest_component, 1);
+            assert_eq!(curve.steps[1].islands, 4);
+            assert_eq!(curve.steps[2].islands, 3);
+        }
+
+        // Biggest subnet (leaves) goes first and the whole group is removed in one step.
+        let unlimited = PercolationConfiguration {
+            max_removed_percentage: 1.0,
+            ..config.clone()
+        };
+        let curve = simulate(
+            "star",
+            &nodes,
+            RemovalStrategy::Subnet,
+            &unlimited,
+            &analysis_config,
+            1,
+        );
+        assert_eq!(curve.steps.len(), 3);
+        assert_eq!(curve.steps[1].removed, 4);
+        assert_eq!(curve.steps[1].largest_component, 1);
+        assert_eq!(curve.steps[2].removed, 5);
+
+        // Leaves are 4 of 5 nodes, more than the limit allows - nothing is removed.
+        let curve = simulate(
+            "star",
+            &nodes,
+            RemovalStrategy::Subnet,
+            &config,
+            &analysis_config,
+            1,
+        );
+        assert_eq!(curve.steps.len(), 1);
+
+        // Nodes without geolocation are never removed by country.
+        let curve = simulate(
+            "star",
+            &nodes,
+            RemovalStrategy::Country,
+            &config,
+            &analysis_config,
+            1,
+        );
+        assert_eq!(curve.steps.len(), 1);
+    }
+
+    #[test]
+    fn csv_field_test() {
+        assert_eq!(csv_field("peers.json"), "peers.json");
+        assert_eq!(csv_field("ips, run 2"), "\"ips, run 2\"");
+        assert_eq!(
+            csv_field("the \"best\"\nlist"),
+            "\"the \"\"best\"\"\nlist\""
+        );
+    }
+
+    #[test]
+    fn nodes_from_peers_test() {
+        let nodes = star();
+        let peers = Peer::generate_all_peerlists(&nodes);
+        let rebuilt = nodes_from_peers(&peers, &nodes);
+
+        assert_eq!(
+            rebuilt.iter().map(|n| &n.connections).collect::<Vec<_>>(),
+            nodes.iter().map(|n| &n.connections).collect::<Vec<_>>()
+        );
+    }
+}
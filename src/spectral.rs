@@ -0,0 +1,319 @@
+//! Spectral robustness metrics.
+//!
+//! Algebraic connectivity (the Fiedler value) is the second smallest eigenvalue of the graph
+//! Laplacian. It is 0 for a disconnected graph and grows as the graph gets harder to cut into
+//! pieces, so it's a single number telling how well connected the whole network is. Crawled
+//! networks always have some isolated or unreachable nodes, so it's computed for the largest
+//! connected component.
+
+use std::collections::VecDeque;
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+/// Maximum number of Lanczos iterations.
+const MAX_LANCZOS_STEPS: usize = 300;
+
+/// Number of iterations between convergence checks.
+const CONVERGENCE_CHECK_STEPS: usize = 10;
+
+/// Norm of the next Lanczos vector below which the Krylov subspace is invariant.
+const TOLERANCE: f64 = 1e-10;
+
+/// Residual norm of the Ritz pair (relative to the estimate, at least 1) below which the
+/// estimate is converged.
+const RESIDUAL_TOLERANCE: f64 = 1e-8;
+
+/// Number of inverse iterations finding the Ritz vector of the estimate.
+const INVERSE_ITERATIONS: usize = 3;
+
+/// Compute algebraic connectivity of the largest connected component of the undirected graph
+/// given as an adjacency list (without duplicates). The Laplacian is never built - Lanczos
+/// iterations only need its product with a vector, which takes time linear in the number of
+/// connections. Returns `None` if the iterations didn't converge.
+pub fn algebraic_connectivity(adjacency: &[Vec<usize>], seed: u64) -> Option<f64> {
+    let component = largest_component(adjacency);
+    if component.len() == adjacency.len() {
+        return lanczos(adjacency, seed, MAX_LANCZOS_STEPS);
+    }
+
+    let mut index = vec![usize::MAX; adjacency.len()];
+    for (new, old) in component.iter().enumerate() {
+        index[*old] = new;
+    }
+    let component = component
+        .iter()
+        .map(|old| adjacency[*old].iter().map(|peer| index[*peer]).collect())
+        .collect::<Vec<Vec<usize>>>();
+    lanczos(&component, seed, MAX_LANCZOS_STEPS)
+}
+
+/// Find the second smallest eigenvalue of the Laplacian of the connected graph with at most
+/// `max_steps` Lanczos iterations.
+fn lanczos(adjacency: &[Vec<usize>], seed: u64, max_steps: usize) -> Option<f64> {
+    let nodes = adjacency.len();
+    if nodes < 2 {
+        return Some(0.0);
+    }
+
+    // Constant vector is the eigenvector of the smallest (zero) eigenvalue, so the iterations
+    // are kept orthogonal to it and the smallest eigenvalue found is the second one.
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut vector = (0..nodes)
+        .map(|_| rng.gen::<f64>() - 0.5)
+        .collect::<Vec<f64>>();
+    remove_mean(&mut vector);
+    normalize(&mut vector);
+
+    // Space orthogonal to the constant vector is exhausted after `nodes - 1` steps.
+    let full_steps = nodes - 1;
+    let max_steps = max_steps.min(full_steps);
+    let mut basis: Vec<Vec<f64>> = Vec::with_capacity(max_steps);
+    let mut alphas = Vec::with_capacity(max_steps);
+    let mut betas: Vec<f64> = Vec::with_capacity(max_steps);
+
+    for step in 0..max_steps {
+        let mut next = laplacian_product(adjacency, &vector);
+        let alpha = dot(&next, &vector);
+        for (value, current) in next.iter_mut().zip(&vector) {
+            *value -= alpha * current;
+        }
+        if let (Some(previous), Some(beta)) = (basis.last(), betas.last()) {
+            for (value, previous) in next.iter_mut().zip(previous) {
+                *value -= beta * previous;
+            }
+        }
+        basis.push(vector);
+        alphas.push(alpha);
+
+        // Full reorthogonalization keeps the basis orthogonal despite rounding errors.
+        remove_mean(&mut next);
+        for base in &basis {
+            let projection = dot(&next, base);
+            for (value, base) in next.iter_mut().zip(base) {
+                *value -= projection * base;
+            }
+        }
+
+        let beta = norm(&next);
+        let invariant = beta < TOLERANCE || step + 1 == full_steps;
+        if invariant || (step + 1) % CONVERGENCE_CHECK_STEPS == 0 || step + 1 == max_steps {
+            let estimate = smallest_tridiagonal_eigenvalue(&alphas, &betas);
+            // Residual of the Ritz pair is the next beta times the last component of the
+            // eigenvector of the tridiagonal matrix.
+            let residual = beta * last_ritz_component(&alphas, &betas, estimate);
+            if invariant || residual <= RESIDUAL_TOLERANCE * estimate.abs().max(1.0) {
+                return Some(estimate.max(0.0));
+            }
+        }
+
+        for value in next.iter_mut() {
+            *value /= beta;
+        }
+        betas.push(beta);
+        vector = next;
+    }
+
+    None
+}
+
+/// Multiply the graph Laplacian (degree matrix minus adjacency matrix) by the vector.
+fn laplacian_product(adjacency: &[Vec<usize>], vector: &[f64]) -> Vec<f64> {
+    adjacency
+        .iter()
+        .enumerate()
+        .map(|(node, peers)| {
+            peers.len() as f64 * vector[node] - peers.iter().map(|&p| vector[p]).sum::<f64>()
+        })
+        .collect()
+}
+
+/// Find the smallest eigenvalue of the symmetric tridiagonal matrix with given diagonal and
+/// off-diagonal values. Uses bisection with Sturm sequence counts.
+fn smallest_tridiagonal_eigenvalue(diagonal: &[f64], off_diagonal: &[f64]) -> f64 {
+    // Gershgorin circles bound all eigenvalues.
+    let radius = |i: usize| {
+        let left = if i > 0 {
+            off_diagonal[i - 1].abs()
+        } else {
+            0.0
+        };
+        let right = off_diagonal.get(i).map(|b| b.abs()).unwrap_or_default();
+        left + right
+    };
+    let mut lower = (0..diagonal.len())
+        .map(|i| diagonal[i] - radius(i))
+        .fold(f64::MAX, f64::min);
+    let mut upper = (0..diagonal.len())
+        .map(|i| diagonal[i] + radius(i))
+        .fold(f64::MIN, f64::max);
+
+    // Number of eigenvalues lower than x equals number of negative pivots of T - xI.
+    let count_below = |x: f64| {
+        let mut count = 0;
+        let mut pivot = 1.0;
+        for (i, value) in diagonal.iter().enumerate() {
+            let coupling = if i > 0 {
+                off_diagonal[i - 1] * off_diagonal[i - 1] / pivot
+            } else {
+                0.0
+            };
+            pivot = value - x - coupling;
+            if pivot == 0.0 {
+                pivot = f64::EPSILON;
+            }
+            if pivot < 0.0 {
+                count += 1;
+            }
+        }
+        count
+    };
+
+    while upper - lower > TOLERANCE * upper.abs().max(1.0) {
+        let middle = (lower + upper) / 2.0;
+        if count_below(middle) >= 1 {
+            upper = middle;
+        } else {
+            lower = middle;
+        }
+    }
+
+    (lower + upper) / 2.0
+}
+
+/// Absolute value of the last component of the normalized eigenvector of the symmetric
+/// tridiagonal matrix for the given eigenvalue. Uses inverse iteration.
+fn last_ritz_component(diagonal: &[f64], off_diagonal: &[f64], eigenvalue: f64) -> f64 {
+    let size = diagonal.len();
+    let mut vector = vec![1.0; size];
+    for _ in 0..INVERSE_ITERATIONS {
+        // Solve (T - eigenvalue * I) x = vector with the Thomas algorithm.
+        let mut upper = vec![0.0; size];
+        let mut solution = vec![0.0; size];
+        for i in 0..size {
+            let (coupling, previous_upper, previous_solution) = if i > 0 {
+                (off_diagonal[i - 1], upper[i - 1], solution[i - 1])
+            } else {
+                (0.0, 0.0, 0.0)
+            };
+            let mut pivot = diagonal[i] - eigenvalue - coupling * previous_upper;
+            if pivot == 0.0 {
+                pivot = f64::EPSILON;
+            }
+            upper[i] = off_diagonal.get(i).copied().unwrap_or_default() / pivot;
+            solution[i] = (vector[i] - coupling * previous_solution) / pivot;
+        }
+        for i in (0..size.saturating_sub(1)).rev() {
+            solution[i] -= upper[i] * solution[i + 1];
+        }
+
+        vector = solution;
+        normalize(&mut vector);
+    }
+
+    vector[size - 1].abs()
+}
+
+/// Nodes of the largest connected component (the first one found of the same size).
+fn largest_component(adjacency: &[Vec<usize>]) -> Vec<usize> {
+    let mut visited = vec![false; adjacency.len()];
+    let mut largest = Vec::new();
+
+    for start in 0..adjacency.len() {
+        if visited[start] {
+            continue;
+        }
+
+        visited[start] = true;
+        let mut component = vec![start];
+        let mut queue = VecDeque::from([start]);
+        while let Some(node) = queue.pop_front() {
+            for &peer in &adjacency[node] {
+                if !visited[peer] {
+                    visited[peer] = true;
+                    component.push(peer);
+                    queue.push_back(peer);
+                }
+            }
+        }
+
+        if component.len() > largest.len() {
+            largest = component;
+        }
+    }
+
+    largest.sort_unstable();
+    largest
+}
+
+fn remove_mean(vector: &mut [f64]) {
+    let mean = vector.iter().sum::<f64>() / vector.len() as f64;
+    for value in vector.iter_mut() {
+        *value -= mean;
+    }
+}
+
+fn normalize(vector: &mut [f64]) {
+    let norm = norm(vector);
+    for value in vector.iter_mut() {
+        *value /= norm;
+    }
+}
+
+fn dot(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+fn norm(vector: &[f64]) -> f64 {
+    dot(vector, vector).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use super::*;
+
+    #[test]
+    fn algebraic_connectivity_test() {
+        // Path with n nodes has algebraic connectivity 2 * (1 - cos(pi / n)).
+        let n = 50;
+        let path = (0..n)
+            .map(|node| {
+                let mut peers = Vec::new();
+                if node > 0 {
+                    peers.push(node - 1);
+                }
+                if node + 1 < n {
+                    peers.push(node + 1);
+                }
+                peers
+            })
+            .collect::<Vec<Vec<usize>>>();
+        let expected = 2.0 * (1.0 - (PI / n as f64).cos());
+        assert!((algebraic_connectivity(&path, 0).unwrap() - expected).abs() < 1e-8);
+
+        // Complete graph with n nodes has algebraic connectivity n.
+        let clique = (0..6)
+            .map(|node| (0..6).filter(|peer| *peer != node).collect())
+            .collect::<Vec<Vec<usize>>>();
+        assert!((algebraic_connectivity(&clique, 3).unwrap() - 6.0).abs() < 1e-8);
+
+        // Isolated nodes don't count, the largest component does.
+        let mut sample = vec![vec![]];
+        sample.extend(
+            path.iter()
+                .map(|peers| peers.iter().map(|peer| peer + 1).collect()),
+        );
+        sample.push(vec![]);
+        assert!((algebraic_connectivity(&sample, 0).unwrap() - expected).abs() < 1e-8);
+
+        // Two-node islands only.
+        let islands = vec![vec![1], vec![0], vec![3], vec![2]];
+        assert!((algebraic_connectivity(&islands, 0).unwrap() - 2.0).abs() < 1e-8);
+        assert_eq!(algebraic_connectivity(&[vec![]], 0), Some(0.0));
+        assert_eq!(algebraic_connectivity(&[], 0), Some(0.0));
+
+        // Too few iterations for the long path.
+        assert_eq!(lanczos(&path, 0, CONVERGENCE_CHECK_STEPS), None);
+    }
+}
@@ -0,0 +1,111 @@
+use std::{
+    io::{self, Write},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use ziggurat_core_crawler::summary::NetworkType;
+
+/// Duration of a single processing phase
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PhaseTiming {
+    /// Name of the phase
+    pub phase: String,
+    /// Network the phase was run for (only for per network phases like IPS ones)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub network: Option<NetworkType>,
+    /// Duration of the phase in seconds
+    pub seconds: f64,
+}
+
+/// Durations of the processing phases in order of their execution.
+#[derive(Default, Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Timings {
+    phases: Vec<PhaseTiming>,
+}
+
+impl Timings {
+    /// Record duration of the phase.
+    pub fn record(&mut self, phase: &str, duration: Duration) {
+        self.phases.push(PhaseTiming {
+            phase: phase.to_owned(),
+            network: None,
+            seconds: duration.as_secs_f64(),
+        });
+    }
+
+    /// Run the closure and record its duration as the phase.
+    pub fn measure<T>(&mut self, phase: &str, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        self.record(phase, start.elapsed());
+        result
+    }
+
+    /// Add duration to the phase - useful for phases interleaved with other work. Phase is
+    /// created if it's not recorded yet.
+    pub fn accumulate(&mut self, phase: &str, duration: Duration) {
+        match self
+            .phases
+            .iter_mut()
+            .find(|timing| timing.phase == phase && timing.network.is_none())
+        {
+            Some(timing) => timing.seconds += duration.as_secs_f64(),
+            None => self.record(phase, duration),
+        }
+    }
+
+    /// Append all phases from other timings, marking them as run for the given network.
+    pub fn extend_for_network(&mut self, other: &Timings, network: NetworkType) {
+        self.phases
+            .extend(other.phases.iter().cloned().map(|timing| PhaseTiming {
+                network: Some(network),
+                ..timing
+            }));
+    }
+
+    /// All recorded phases.
+    pub fn phases(&self) -> &[PhaseTiming] {
+        &self.phases
+    }
+
+    /// Total duration of all phases in seconds.
+    pub fn total(&self) -> f64 {
+        self.phases.iter().map(|timing| timing.seconds).sum()
+    }
+
+    /// Print phases with their durations to given output.
+    pub fn print(&self, o: &mut Box<dyn Write>) -> io::Result<()> {
+        writeln!(o, "Timings:")?;
+        for timing in &self.phases {
+            writeln!(o, "{}: {:.3} s", timing.phase, timing.seconds)?;
+        }
+        writeln!(o, "Total: {:.3} s", self.total())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timings_test() {
+        let mut timings = Timings::default();
+        timings.record("load", Duration::from_millis(500));
+        timings.accumulate("geolocation", Duration::from_millis(250));
+        timings.accumulate("geolocation", Duration::from_millis(250));
+        let value = timings.measure("noop", || 42);
+
+        assert_eq!(value, 42);
+        assert_eq!(timings.phases().len(), 3);
+        assert_eq!(timings.phases()[1].phase, "geolocation");
+        assert!((timings.phases()[1].seconds - 0.5).abs() < 0.0001);
+        assert!(timings.total() >= 1.0);
+
+        let mut all = Timings::default();
+        all.extend_for_network(&timings, NetworkType::Zcash);
+        assert_eq!(all.phases().len(), 3);
+        assert!(all.phases()[0].network == Some(NetworkType::Zcash));
+    }
+}